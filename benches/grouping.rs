@@ -0,0 +1,160 @@
+// benchmarks of the scan path (walking, normalizing and grouping) over synthetic trees
+//
+// run with `cargo bench`, optionally followed by `-- <filter>` to only run matching
+// benchmarks. UNIQUER_BENCH_SCALE multiplies the size of the trees (defaults to 1).
+// `-- --save-baseline <file>` saves the median times, `-- --baseline <file>` compares them
+// with a saved baseline and fails if a benchmark got more than 20% slower.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use uniquer::keep::{KeepOrder, KeepPolicy};
+use uniquer::normalize::{Normalizer, Pattern, COMPOUND_EXTENSIONS};
+use uniquer::plan::plan_deletions;
+use uniquer::{OnConflict, Remove, Scanner};
+
+const ITERATIONS: usize = 10;
+// how much slower than the baseline a benchmark may get before it counts as a regression
+const REGRESSION_THRESHOLD: f64 = 1.2;
+
+// the default patterns of the command line
+const DEFAULT_PATTERNS: &[Pattern] = &[Pattern::Numbered, Pattern::NumberedNoSpace];
+const ALL_PATTERNS: &[Pattern] = &[
+    Pattern::Numbered, Pattern::NumberedNoSpace, Pattern::WindowsCopy, Pattern::MacosCopy,
+    Pattern::Underscore, Pattern::Dash, Pattern::CopyOf,
+];
+
+// a synthetic tree and how to scan it
+struct Benchmark {
+    name: &'static str,
+    build: fn(&Path, usize),
+    patterns: &'static [Pattern],
+    cross_directory: bool,
+}
+
+const BENCHMARKS: &[Benchmark] = &[
+    Benchmark { name: "flat_unique_names", build: flat_unique_names, patterns: DEFAULT_PATTERNS, cross_directory: false },
+    Benchmark { name: "nested_name_collisions", build: nested_name_collisions, patterns: DEFAULT_PATTERNS, cross_directory: false },
+    Benchmark { name: "nested_name_collisions_cross_directory", build: nested_name_collisions, patterns: DEFAULT_PATTERNS, cross_directory: true },
+    Benchmark { name: "numbered_copies", build: numbered_copies, patterns: DEFAULT_PATTERNS, cross_directory: false },
+    Benchmark { name: "all_patterns", build: nested_name_collisions, patterns: ALL_PATTERNS, cross_directory: false },
+];
+
+// 20000 files with distinct names in a single directory
+fn flat_unique_names(root: &Path, scale: usize) {
+    for i in 0..20_000 * scale {
+        fs::write(root.join(format!("file-{}.txt", i)), b"").unwrap();
+    }
+}
+
+// 1000 directories with 10 numbered names each, all of different sizes (never hashed)
+fn nested_name_collisions(root: &Path, scale: usize) {
+    for d in 0..1000 * scale {
+        let dir = root.join(format!("dir-{}", d % 100)).join(format!("sub-{}", d));
+        fs::create_dir_all(&dir).unwrap();
+        for i in 0..10 {
+            let name = if i == 0 { "notes.txt".to_string() } else { format!("notes ({}).txt", i) };
+            fs::write(dir.join(name), vec![b'x'; i]).unwrap();
+        }
+    }
+}
+
+// 2000 groups of identical small files (hashed and verified)
+fn numbered_copies(root: &Path, scale: usize) {
+    for g in 0..2000 * scale {
+        let contents = format!("contents of group {}", g);
+        for i in 0..3 {
+            let name = if i == 0 { format!("photo-{}.jpg", g) } else { format!("photo-{} ({}).jpg", g, i) };
+            fs::write(root.join(name), &contents).unwrap();
+        }
+    }
+}
+
+fn main() {
+    let mut filter: Vec<String> = Vec::new();
+    let mut save_baseline: Option<PathBuf> = None;
+    let mut baseline_path: Option<PathBuf> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--save-baseline" => save_baseline = Some(args.next().expect("--save-baseline needs a file").into()),
+            "--baseline" => baseline_path = Some(args.next().expect("--baseline needs a file").into()),
+            // (cargo passes --bench)
+            arg if arg.starts_with('-') => {}
+            _ => filter.push(arg),
+        }
+    }
+    let scale = std::env::var("UNIQUER_BENCH_SCALE").ok().and_then(|s| s.parse().ok()).unwrap_or(1);
+    let baseline = baseline_path.as_deref().map(load_baseline).unwrap_or_default();
+    let mut medians: Vec<(&str, Duration)> = Vec::new();
+    let mut regressions = Vec::new();
+
+    for benchmark in BENCHMARKS {
+        if !filter.is_empty() && !filter.iter().any(|f| benchmark.name.contains(f.as_str())) {
+            continue;
+        }
+
+        let root = std::env::temp_dir().join(format!("uniquer-bench-{}-{}", std::process::id(), benchmark.name));
+        fs::create_dir_all(&root).unwrap();
+        (benchmark.build)(&root, scale);
+
+        let mut times: Vec<Duration> = (0..ITERATIONS).map(|_| run(benchmark, &root)).collect();
+        times.sort();
+        let median = times[ITERATIONS / 2];
+        let mut line = format!(
+            "{:<40} min {:>10.2?}  median {:>10.2?}  max {:>10.2?}",
+            benchmark.name,
+            times[0],
+            median,
+            times[ITERATIONS - 1]
+        );
+        if let Some(&base) = baseline.get(benchmark.name) {
+            let ratio = median.as_secs_f64() / base.as_secs_f64();
+            line.push_str(&format!("  {:+.1}% vs baseline", (ratio - 1.0) * 100.0));
+            if ratio > REGRESSION_THRESHOLD {
+                line.push_str(" (regression)");
+                regressions.push(benchmark.name);
+            }
+        }
+        println!("{}", line);
+        medians.push((benchmark.name, median));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    if let Some(path) = save_baseline {
+        let contents: String = medians.iter().map(|(name, median)| format!("{}\t{}\n", name, median.as_nanos())).collect();
+        fs::write(&path, contents).unwrap_or_else(|e| panic!("can't save the baseline to {}: {}", path.display(), e));
+    }
+    if !regressions.is_empty() {
+        eprintln!("regressed against the baseline: {}", regressions.join(", "));
+        std::process::exit(1);
+    }
+}
+
+// the median times of a baseline saved with --save-baseline, by benchmark name
+fn load_baseline(path: &Path) -> HashMap<String, Duration> {
+    let contents = fs::read_to_string(path).unwrap_or_else(|e| panic!("can't read the baseline {}: {}", path.display(), e));
+    contents.lines()
+        .filter_map(|line| {
+            let (name, nanos) = line.split_once('\t')?;
+            Some((name.to_string(), Duration::from_nanos(nanos.parse().ok()?)))
+        })
+        .collect()
+}
+
+// time scanning the tree and planning the deletions (without applying them)
+fn run(benchmark: &Benchmark, root: &Path) -> Duration {
+    let start = Instant::now();
+    let compound_extensions: Vec<String> = COMPOUND_EXTENSIONS.iter().map(|ext| ext.to_string()).collect();
+    let normalizer = Normalizer::new(benchmark.patterns, Vec::new(), &compound_extensions);
+    let scanner = Scanner::new(normalizer.clone()).cross_directory(benchmark.cross_directory);
+    let mut errors = Vec::new();
+    let groups = scanner.scan(&[root.to_path_buf()], &mut errors).unwrap();
+    let keep_order = KeepOrder::new(vec![KeepPolicy::Newest], Vec::new(), normalizer.clone());
+    let plan = plan_deletions(groups.duplicates, &Remove, &normalizer, &keep_order, true, OnConflict::KeepNumbered);
+    let elapsed = start.elapsed();
+    assert!(errors.is_empty(), "scanning failed: {:?}", errors);
+    std::hint::black_box(plan);
+    elapsed
+}
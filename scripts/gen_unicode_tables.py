@@ -0,0 +1,55 @@
+#!/usr/bin/env python3
+# generates src/unicode_tables.rs from the unicode database of python's unicodedata module
+#
+#     python3 scripts/gen_unicode_tables.py > src/unicode_tables.rs
+import sys
+import unicodedata
+
+# hangul syllables are decomposed algorithmically
+HANGUL = range(0xAC00, 0xD7A4)
+
+
+def canonical_decomposition(c):
+    decomposition = unicodedata.decomposition(c)
+    if not decomposition or decomposition.startswith("<"):
+        return None
+    result = []
+    for part in decomposition.split():
+        char = chr(int(part, 16))
+        result.extend(canonical_decomposition(char) or [char])
+    return result
+
+
+def main():
+    decompositions = []
+    ranges = []
+    for code in range(sys.maxunicode + 1):
+        c = chr(code)
+        if code not in HANGUL:
+            decomposition = canonical_decomposition(c)
+            if decomposition:
+                decompositions.append((code, decomposition))
+        ccc = unicodedata.combining(c)
+        if ccc:
+            if ranges and ranges[-1][1] == code - 1 and ranges[-1][2] == ccc:
+                ranges[-1][1] = code
+            else:
+                ranges.append([code, code, ccc])
+
+    print("// generated by scripts/gen_unicode_tables.py from unicode %s, do not edit" % unicodedata.unidata_version)
+    print()
+    print("// the full canonical decompositions, sorted by code point (hangul syllables excluded)")
+    print("pub const DECOMPOSITIONS: &[(char, &[char])] = &[")
+    for code, decomposition in decompositions:
+        chars = ", ".join("'\\u{%x}'" % ord(d) for d in decomposition)
+        print("    ('\\u{%x}', &[%s])," % (code, chars))
+    print("];")
+    print()
+    print("// the non-zero canonical combining classes as sorted, inclusive ranges")
+    print("pub const COMBINING_CLASSES: &[(char, char, u8)] = &[")
+    for start, end, ccc in ranges:
+        print("    ('\\u{%x}', '\\u{%x}', %d)," % (start, end, ccc))
+    print("];")
+
+
+main()
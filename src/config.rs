@@ -0,0 +1,272 @@
+// the configuration file (~/.config/uniquer/config.toml)
+//
+// only the subset of toml needed by the configuration is supported:
+//
+//     # extensions made of several parts (replacing the built-in list)
+//     compound_extensions = [".tar.gz", ".d.ts"]
+//
+//     # a user defined naming pattern of copies, with "base" and "ext" captures
+//     [[pattern]]
+//     name = "edited"
+//     regex = '^(?P<base>IMG_\d+)-edited(?:-\d+)?(?P<ext>\.\w+)$'
+use std::io;
+use std::path::{Path, PathBuf};
+use regex::bytes::Regex;
+use crate::error::{Error, Result};
+use crate::{normalize, xdg};
+
+#[derive(Debug, Default)]
+pub struct Config {
+    // the naming patterns declared by the user (validated to have "base" and "ext" captures)
+    pub patterns: Vec<Regex>,
+    // replaces the built-in list of compound extensions
+    pub compound_extensions: Option<Vec<String>>,
+}
+
+// the table a key = value line belongs to
+enum Section {
+    Root,
+    Pattern { line: usize, name: Option<String>, regex: Option<String> },
+}
+
+// a parsed value
+enum Value {
+    String(String),
+    Array(Vec<String>),
+}
+
+// $XDG_CONFIG_HOME/uniquer/config.toml, defaulting to ~/.config/uniquer/config.toml
+pub fn default_path() -> Option<PathBuf> {
+    xdg::base_dir("XDG_CONFIG_HOME", ".config").ok().map(|dir| dir.join("uniquer/config.toml"))
+}
+
+// load the configuration, an explicitly given file has to exist while the default one is optional
+pub fn load(path: Option<&Path>) -> Result<Config> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => parse(&contents).map_err(|message| Error::Config { path, message }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+        Err(e) => Err(Error::io(path, e)),
+    }
+}
+
+// parse and validate the contents of a configuration file
+fn parse(contents: &str) -> std::result::Result<Config, String> {
+    let mut config = Config::default();
+    let mut section = Section::Root;
+
+    // an array spanning several lines, together with the line it started on
+    let mut pending: Option<(usize, String)> = None;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let (number, line) = match pending.take() {
+            Some((number, mut joined)) => {
+                joined.push(' ');
+                joined.push_str(line);
+                (number, joined)
+            }
+            None => (index + 1, line.to_string()),
+        };
+        let line = line.as_str();
+        if line.is_empty() {
+            continue;
+        }
+        let opens_array = line.split_once('=').is_some_and(|(_, value)| value.trim().starts_with('['));
+        if opens_array && !line.ends_with(']') {
+            pending = Some((number, line.to_string()));
+            continue;
+        }
+
+        if line.starts_with('[') {
+            finish_section(section, &mut config)?;
+            section = match line {
+                "[[pattern]]" => Section::Pattern { line: number, name: None, regex: None },
+                _ => return Err(format!("line {}: unknown table {}", number, line)),
+            };
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| format!("line {}: expected key = value", number))?;
+        let key = key.trim();
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", number, e))?;
+        match (&mut section, key, value) {
+            (Section::Root, "compound_extensions", Value::Array(extensions)) => {
+                let extensions = extensions.iter()
+                    .map(|ext| normalize::check_extension(ext))
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(|e| format!("line {}: {}", number, e))?;
+                config.compound_extensions = Some(extensions);
+            }
+            (Section::Pattern { name, .. }, "name", Value::String(value)) => *name = Some(value),
+            (Section::Pattern { regex, .. }, "regex", Value::String(value)) => *regex = Some(value),
+            _ => return Err(format!("line {}: unknown key {} (or a value of the wrong type)", number, key)),
+        }
+    }
+    if let Some((number, _)) = pending {
+        return Err(format!("line {}: unterminated array", number));
+    }
+    finish_section(section, &mut config)?;
+    Ok(config)
+}
+
+// validate a finished table and add it to the configuration
+fn finish_section(section: Section, config: &mut Config) -> std::result::Result<(), String> {
+    if let Section::Pattern { line, name, regex } = section {
+        let name = name.ok_or_else(|| format!("line {}: pattern without a name", line))?;
+        let regex = regex.ok_or_else(|| format!("line {}: pattern '{}' without a regex", line, name))?;
+        let regex = Regex::new(&regex).map_err(|e| format!("line {}: invalid regex of pattern '{}':\n{}", line, name, e))?;
+        for group in ["base", "ext"] {
+            if !regex.capture_names().flatten().any(|n| n == group) {
+                return Err(format!("line {}: the regex of pattern '{}' has no (?P<{}>...) capture group", line, name, group));
+            }
+        }
+        config.patterns.push(regex);
+    }
+    Ok(())
+}
+
+// remove a trailing comment (outside of strings)
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(value: &str) -> std::result::Result<Value, String> {
+    let Some(mut rest) = value.strip_prefix('[') else {
+        let (string, rest) = parse_string(value)?;
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected {} after the value", rest.trim()));
+        }
+        return Ok(Value::String(string));
+    };
+
+    // an array of strings, optionally with a trailing comma
+    let mut array = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(']') {
+            if !after.trim().is_empty() {
+                return Err(format!("unexpected {} after the value", after.trim()));
+            }
+            return Ok(Value::Array(array));
+        }
+        let (string, after) = parse_string(rest)?;
+        array.push(string);
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after;
+        } else if !rest.starts_with(']') {
+            return Err("expected , or ] in the array".to_string());
+        }
+    }
+}
+
+// parse a basic ("...") or literal ('...') string, returning it together with the rest of the input
+fn parse_string(input: &str) -> std::result::Result<(String, &str), String> {
+    if let Some(literal) = input.strip_prefix('\'') {
+        let end = literal.find('\'').ok_or("unterminated string")?;
+        return Ok((literal[..end].to_string(), &literal[end + 1..]));
+    }
+    let basic = input.strip_prefix('"').ok_or("expected a string")?;
+    let mut string = String::new();
+    let mut chars = basic.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &basic[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('\\') => string.push('\\'),
+                Some('"') => string.push('"'),
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some(c) => return Err(format!("unknown escape \\{} (use a 'literal string' for regexes)", c)),
+                None => break,
+            },
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        let config = parse(concat!(
+            "# a comment\n",
+            "compound_extensions = [\".tar.gz\", '.d.ts', ] # trailing comment\n",
+            "\n",
+            "[[pattern]]\n",
+            "name = \"edited # not a comment\"\n",
+            "regex = '^(?P<base>IMG_\\d+)-edited(?P<ext>\\.\\w+)$'\n",
+            "[[pattern]]\n",
+            "name = \"quoted \\\"name\\\"\"\n",
+            "regex = \"^(?P<base>.+)~(?P<ext>)$\"\n",
+        ))
+        .unwrap();
+        assert_eq!(config.compound_extensions, Some(vec![".tar.gz".to_string(), ".d.ts".to_string()]));
+        let regexes: Vec<&str> = config.patterns.iter().map(Regex::as_str).collect();
+        assert_eq!(regexes, [r"^(?P<base>IMG_\d+)-edited(?P<ext>\.\w+)$", "^(?P<base>.+)~(?P<ext>)$"]);
+    }
+
+    #[test]
+    fn multiline_array() {
+        let config = parse("compound_extensions = [\n    \".tar.gz\", # gzip\n    \".min.js\",\n]\n").unwrap();
+        assert_eq!(config.compound_extensions, Some(vec![".tar.gz".to_string(), ".min.js".to_string()]));
+    }
+
+    #[test]
+    fn empty() {
+        let config = parse("\n# nothing\n").unwrap();
+        assert!(config.patterns.is_empty());
+        assert_eq!(config.compound_extensions, None);
+    }
+
+    #[test]
+    fn errors() {
+        let cases: &[(&str, &str)] = &[
+            ("compound_extensions = [\".tar.gz\"", "line 1: unterminated array"),
+            ("compound_extensions = [\n\".tar.gz\",\n", "line 1: unterminated array"),
+            ("compound_extensions = [\"tar.gz\"]", "line 1: invalid extension 'tar.gz'"),
+            ("compound_extensions = \".tar.gz\"", "line 1: unknown key compound_extensions"),
+            ("compound_extensions = [\".a\" \".b\"]", "line 1: expected , or ]"),
+            ("unknown = 'x'", "line 1: unknown key unknown"),
+            ("just a line", "line 1: expected key = value"),
+            ("\n[table]", "line 2: unknown table [table]"),
+            ("[[pattern]]\nname = 'x'", "line 1: pattern 'x' without a regex"),
+            ("[[pattern]]\nregex = '(?P<base>.)(?P<ext>.)'", "line 1: pattern without a name"),
+            ("[[pattern]]\nname = 'x'\nregex = '(?P<base>.+)'", "line 1: the regex of pattern 'x' has no (?P<ext>...) capture group"),
+            ("[[pattern]]\nname = 'x'\nregex = '('", "line 1: invalid regex of pattern 'x'"),
+            ("[[pattern]]\nname = \"a\\d\"", "line 2: unknown escape \\d"),
+            ("[[pattern]]\nname = 'x' 'y'", "line 2: unexpected 'y' after the value"),
+            ("[[pattern]]\nname = \"x", "line 2: unterminated string"),
+            ("[[pattern]]\nname = x", "line 2: expected a string"),
+        ];
+        for &(contents, expected) in cases {
+            let error = parse(contents).unwrap_err();
+            assert!(error.starts_with(expected), "{:?} gave {:?}", contents, error);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// exit codes of the process
+pub const EXIT_HANDLED: i32 = 0;
+pub const EXIT_NO_DUPLICATES: i32 = 1;
+pub const EXIT_FATAL: i32 = 2;
+pub const EXIT_PARTIAL_FAILURE: i32 = 3;
+
+// the errors of a run
+#[derive(Debug)]
+pub enum Error {
+    // a filesystem operation on a path failed
+    Io { path: PathBuf, source: io::Error },
+    // an entry of the directory tree couldn't be read
+    Walk(walkdir::Error),
+    // the journal couldn't be written, so no further change can be undone
+    Journal(io::Error),
+    // the configuration file is invalid
+    Config { path: PathBuf, message: String },
+}
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io { path: path.into(), source }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Walk(e) => write!(f, "{}", e),
+            Error::Journal(e) => write!(f, "could not write the journal: {}", e),
+            Error::Config { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Walk(e) => Some(e),
+            Error::Journal(e) => Some(e),
+            Error::Config { .. } => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,239 @@
+// choosing the files to scan with gitignore-style globs (or regexes) matched against the path
+// relative to the root
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use regex::bytes::Regex;
+
+// any byte, so paths that aren't valid utf-8 are matched as well
+const ANY: &str = r"(?s-u:.)";
+// a character of a path segment, or a single byte that isn't part of valid utf-8
+const SEGMENT_CHAR: &str = r"(?:[^/]|(?-u:[\x80-\xff]))";
+
+// a glob or regex for relative paths
+#[derive(Debug, Clone)]
+pub struct PathRule {
+    regex: Regex,
+    // only matches directories (a glob ending in "/", or given to --exclude-dir)
+    dir_only: bool,
+}
+
+impl PathRule {
+    // a gitignore-style glob: without a "/" (other than a trailing one) it matches the name at
+    // any depth, otherwise the whole relative path. "*" and "?" don't match "/", "**" matches
+    // any number of directories and a trailing "/" only matches directories
+    pub fn glob(glob: &str) -> Result<Self, String> {
+        let (glob, dir_only) = match glob.strip_suffix('/') {
+            Some(dir) => (dir, true),
+            None => (glob, false),
+        };
+        if glob.is_empty() {
+            return Err("empty glob".to_string());
+        }
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        let mut regex = if anchored { "^".to_string() } else { format!("^(?:{ANY}*/)?") };
+        regex.push_str(&glob_regex(glob)?);
+        regex.push('$');
+        let regex = Regex::new(&regex).map_err(|e| format!("invalid glob '{}': {}", glob, e))?;
+        Ok(PathRule { regex, dir_only })
+    }
+
+    // a glob that only matches directories
+    pub fn dir_glob(glob: &str) -> Result<Self, String> {
+        Ok(PathRule { dir_only: true, ..PathRule::glob(glob)? })
+    }
+
+    // a regular expression, searched for in the relative path
+    pub fn regex(regex: &str) -> Result<Self, String> {
+        let regex = Regex::new(regex).map_err(|e| e.to_string())?;
+        Ok(PathRule { regex, dir_only: false })
+    }
+
+    fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.regex.is_match(relative.as_os_str().as_bytes())
+    }
+}
+
+// the include and exclude rules of a scan
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    // files have to match one of them (if there are any), directories are always descended into
+    includes: Vec<PathRule>,
+    // files and directories matching any of them are skipped, with everything inside
+    excludes: Vec<PathRule>,
+}
+
+impl Filter {
+    pub fn new(includes: Vec<PathRule>, excludes: Vec<PathRule>) -> Self {
+        Filter { includes, excludes }
+    }
+
+    // whether an entry below the root (given relative to it) is scanned
+    pub fn allows(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.excludes.iter().any(|rule| rule.matches(relative, is_dir)) {
+            return false;
+        }
+        is_dir || self.includes.is_empty() || self.includes.iter().any(|rule| rule.matches(relative, false))
+    }
+}
+
+// translate a glob (without its anchoring) to a regular expression
+fn glob_regex(glob: &str) -> Result<String, String> {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    // whether the last character was a "/" (or the start of the glob)
+    let mut at_separator = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                match chars.peek() {
+                    // "**/" matches any number of directories (including none)
+                    Some('/') if at_separator => {
+                        chars.next();
+                        regex.push_str(&format!("(?:{ANY}*/)?"));
+                        continue;
+                    }
+                    _ => regex.push_str(&format!("{ANY}*")),
+                }
+            }
+            '*' => regex.push_str(&format!("{SEGMENT_CHAR}*")),
+            '?' => regex.push_str(SEGMENT_CHAR),
+            '[' => {
+                // bytes that aren't valid utf-8 can't be in the class, so a negated one matches them
+                let negated = chars.next_if(|&c| c == '!' || c == '^').is_some();
+                regex.push_str(if negated { r"(?:(?-u:[\x80-\xff])|[^" } else { "(?:[" });
+                // a "]" right at the start is part of the class, like in gitignore
+                let mut first = true;
+                loop {
+                    match chars.next() {
+                        Some(']') if !first => break,
+                        Some(c) => {
+                            let (c, escaped) = match c {
+                                '\\' => (chars.next().ok_or_else(|| format!("unterminated [ in glob '{}'", glob))?, true),
+                                c => (c, false),
+                            };
+                            // punctuation like "[", "&" or "~" means something in regex classes
+                            // ("-" stays a range unless it was escaped, "<" and ">" can't be escaped)
+                            if c.is_ascii_punctuation() && !matches!(c, '<' | '>') && (escaped || c != '-') {
+                                regex.push('\\');
+                            }
+                            regex.push(c);
+                        }
+                        None => return Err(format!("unterminated [ in glob '{}'", glob)),
+                    }
+                    first = false;
+                }
+                regex.push_str("])");
+            }
+            '\\' => match chars.next() {
+                Some(escaped) => regex.push_str(&regex::escape(&escaped.to_string())),
+                None => return Err(format!("glob '{}' ends with a \\", glob)),
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        at_separator = c == '/';
+    }
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn path(bytes: &[u8]) -> &Path {
+        Path::new(OsStr::from_bytes(bytes))
+    }
+
+    #[test]
+    fn globs() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*.txt", "a.txt", true),
+            ("*.txt", "dir/sub/a.txt", true),
+            ("*.txt", "a.txt.bak", false),
+            ("/a.txt", "a.txt", true),
+            ("/a.txt", "dir/a.txt", false),
+            ("dir/*.txt", "dir/a.txt", true),
+            ("dir/*.txt", "dir/sub/a.txt", false),
+            ("dir/**/*.txt", "dir/a.txt", true),
+            ("dir/**/*.txt", "dir/sub/deep/a.txt", true),
+            ("**/build", "build", true),
+            ("**/build", "x/y/build", true),
+            ("build/**", "build/a/b", true),
+            ("build/**", "build", false),
+            ("a**b", "a/x/b", true),
+            ("?.jpg", "a.jpg", true),
+            ("?.jpg", "ab.jpg", false),
+            ("?", "/", false),
+            ("[ab].jpg", "b.jpg", true),
+            ("[a-c].jpg", "b.jpg", true),
+            ("[!ab].jpg", "c.jpg", true),
+            ("[!ab].jpg", "a.jpg", false),
+            ("[^ab].jpg", "a.jpg", false),
+            ("[]]", "]", true),
+            ("[\\-x]", "-", true),
+            ("[\\-x]", "a", false),
+            ("[\\d]", "d", true),
+            ("[\\d]", "5", false),
+            ("[&~<]", "~", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("a+b(1).txt", "a+b(1).txt", true),
+            ("café", "dir/café", true),
+        ];
+        for &(glob, relative, expected) in cases {
+            let rule = PathRule::glob(glob).unwrap();
+            assert_eq!(rule.matches(Path::new(relative), false), expected, "{} on {}", glob, relative);
+        }
+    }
+
+    #[test]
+    fn invalid_globs() {
+        for glob in ["", "/", "[abc", "[", "a\\", "[a\\"] {
+            assert!(PathRule::glob(glob).is_err(), "{}", glob);
+        }
+    }
+
+    #[test]
+    fn non_utf8_paths() {
+        // "café.txt" in latin-1
+        let latin1 = path(b"dir/caf\xe9.txt");
+        for glob in ["*.txt", "caf?.txt", "caf[!a].txt", "dir/*", "dir/**", "**/*.txt"] {
+            assert!(PathRule::glob(glob).unwrap().matches(latin1, false), "{}", glob);
+        }
+        assert!(!PathRule::glob("caf[e].txt").unwrap().matches(latin1, false));
+        assert!(PathRule::glob("*.txt").unwrap().matches(path(b"\xff\n/a.txt"), false));
+
+        let include = Filter::new(vec![PathRule::glob("*.txt").unwrap()], Vec::new());
+        assert!(include.allows(latin1, false));
+        let exclude = Filter::new(Vec::new(), vec![PathRule::glob("*.txt").unwrap()]);
+        assert!(!exclude.allows(latin1, false));
+    }
+
+    #[test]
+    fn directories() {
+        let trailing = PathRule::glob("build/").unwrap();
+        assert!(trailing.matches(Path::new("a/build"), true));
+        assert!(!trailing.matches(Path::new("a/build"), false));
+        let dir = PathRule::dir_glob("node_modules").unwrap();
+        assert!(dir.matches(Path::new("node_modules"), true));
+        assert!(!dir.matches(Path::new("node_modules"), false));
+
+        // includes never keep the walk out of a directory
+        let filter = Filter::new(vec![PathRule::glob("*.jpg").unwrap()], vec![dir]);
+        assert!(filter.allows(Path::new("photos"), true));
+        assert!(!filter.allows(Path::new("photos/a.png"), false));
+        assert!(filter.allows(Path::new("photos/a.jpg"), false));
+        assert!(!filter.allows(Path::new("a/node_modules"), true));
+    }
+
+    #[test]
+    fn regexes() {
+        let rule = PathRule::regex(r"^tmp/|\.bak$").unwrap();
+        assert!(rule.matches(Path::new("tmp/a"), false));
+        assert!(rule.matches(Path::new("a/b.bak"), false));
+        assert!(!rule.matches(Path::new("a/tmp/b"), false));
+        assert!(PathRule::regex("(").is_err());
+    }
+}
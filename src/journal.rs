@@ -0,0 +1,505 @@
+// append-only journal of all changes made to the filesystem, used to undo previous runs
+//
+// every line is one entry with tab separated fields:
+// run, timestamp, kind, original path, target path, size, content hash
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::hash::{self, Digest};
+use crate::{link, scan, sys, trash, xdg};
+
+// the kind of change an entry records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    // the file was deleted, the target is the kept file with the same contents
+    Remove,
+    // the file was moved to the trash, the target is its location inside the trash
+    Trash,
+    // the file was replaced by a hard link to the target
+    HardLink,
+    // the file was replaced by a symbolic link to the target
+    Symlink,
+    // the file was renamed to the target
+    Rename,
+    // the entry of the run with the same paths was undone, by an undo that may have stopped halfway
+    Restored,
+    // the run was undone (the paths are unused)
+    Undone,
+}
+
+impl EntryKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::Remove => "remove",
+            EntryKind::Trash => "trash",
+            EntryKind::HardLink => "hardlink",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Rename => "rename",
+            EntryKind::Restored => "restored",
+            EntryKind::Undone => "undone",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<EntryKind> {
+        match kind {
+            "remove" => Some(EntryKind::Remove),
+            "trash" => Some(EntryKind::Trash),
+            "hardlink" => Some(EntryKind::HardLink),
+            "symlink" => Some(EntryKind::Symlink),
+            "rename" => Some(EntryKind::Rename),
+            "restored" => Some(EntryKind::Restored),
+            "undone" => Some(EntryKind::Undone),
+            _ => None,
+        }
+    }
+}
+
+// a single line of the journal
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub run: String,
+    pub timestamp: String,
+    pub kind: EntryKind,
+    pub path: PathBuf,
+    pub target: PathBuf,
+    pub size: u64,
+    pub hash: Option<Digest>,
+}
+
+// writer for the entries of the current run (the file is only created once something is recorded)
+pub struct Journal {
+    path: PathBuf,
+    run: String,
+    file: Option<File>,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        let seconds = unix_time();
+        Journal {
+            path,
+            run: format!("{}-{}", seconds, std::process::id()),
+            file: None,
+        }
+    }
+
+    // append an entry for the current run
+    pub fn record(&mut self, kind: EntryKind, path: &Path, target: &Path, size: u64, hash: Option<&Digest>) -> io::Result<()> {
+        if self.file.is_none() {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        }
+
+        let mut line = Vec::new();
+        line.extend_from_slice(self.run.as_bytes());
+        line.push(b'\t');
+        line.extend_from_slice(sys::format_local_time(unix_time())?.as_bytes());
+        line.push(b'\t');
+        line.extend_from_slice(kind.as_str().as_bytes());
+        line.push(b'\t');
+        line.extend_from_slice(&escape(&std::path::absolute(path)?));
+        line.push(b'\t');
+        line.extend_from_slice(&escape(&std::path::absolute(target)?));
+        line.push(b'\t');
+        line.extend_from_slice(size.to_string().as_bytes());
+        line.push(b'\t');
+        line.extend_from_slice(hash.map_or("-".to_string(), hash::to_hex).as_bytes());
+        line.push(b'\n');
+
+        // write the whole line at once, so entries of concurrent runs don't interleave
+        let file = self.file.as_mut().unwrap();
+        file.write_all(&line)?;
+        file.flush()
+    }
+}
+
+// $XDG_STATE_HOME/uniquer/journal, defaulting to ~/.local/state/uniquer/journal
+pub fn default_path() -> io::Result<PathBuf> {
+    Ok(state_dir()?.join("journal"))
+}
+
+// $XDG_STATE_HOME/uniquer, defaulting to ~/.local/state/uniquer
+pub(crate) fn state_dir() -> io::Result<PathBuf> {
+    Ok(xdg::base_dir("XDG_STATE_HOME", ".local/state")?.join("uniquer"))
+}
+
+// read all entries of the journal
+pub fn read_entries(path: &Path) -> io::Result<Vec<Entry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (number, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = parse_entry(&line).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: malformed journal entry", path.display(), number + 1))
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn parse_entry(line: &[u8]) -> Option<Entry> {
+    let fields: Vec<&[u8]> = line.split(|&b| b == b'\t').collect();
+    if fields.len() != 7 {
+        return None;
+    }
+    let text = |field: &[u8]| std::str::from_utf8(field).ok().map(str::to_string);
+    Some(Entry {
+        run: text(fields[0])?,
+        timestamp: text(fields[1])?,
+        kind: EntryKind::parse(&text(fields[2])?)?,
+        path: unescape(fields[3]),
+        target: unescape(fields[4]),
+        size: text(fields[5])?.parse().ok()?,
+        hash: match fields[6] {
+            b"-" => None,
+            hex => Some(hash::from_hex(&text(hex)?)?),
+        },
+    })
+}
+
+// the outcome of undoing a run
+#[derive(Debug)]
+pub struct Undone {
+    pub run: String,
+    pub timestamp: String,
+    // the entries replayed so far, in the order they were undone
+    pub restored: Vec<Entry>,
+    // what stopped the undo halfway (the run isn't marked as undone then)
+    pub error: Option<io::Error>,
+}
+
+// restore the files changed by a run (the last run that wasn't undone by default)
+//
+// an error is returned if the run can't be undone at all, without changing anything
+pub fn undo(journal: &mut Journal, run: Option<&str>) -> io::Result<Undone> {
+    let entries = match read_entries(&journal.path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        result => result?,
+    };
+    let undone: HashSet<&str> = entries.iter()
+        .filter(|e| e.kind == EntryKind::Undone)
+        .map(|e| e.run.as_str())
+        .collect();
+
+    let run = match run {
+        Some(run) if undone.contains(run) => {
+            return Err(io::Error::other(format!("run {} was already undone", run)));
+        }
+        Some(run) => run.to_string(),
+        None => match entries.iter().rev().find(|e| !undone.contains(e.run.as_str())) {
+            Some(entry) => entry.run.clone(),
+            None => return Err(io::Error::other("there is no run to undo")),
+        },
+    };
+    let changes: Vec<&Entry> = entries.iter().filter(|e| e.run == run).collect();
+    if changes.is_empty() {
+        return Err(io::Error::other(format!("run {} is not in the journal", run)));
+    }
+    // an undo that stopped halfway restored the last changes of the run, those are done already
+    let restored = changes.iter().filter(|e| e.kind == EntryKind::Restored).count();
+    let mut pending: Vec<&Entry> = changes.iter().filter(|e| !matches!(e.kind, EntryKind::Restored | EntryKind::Undone)).copied().collect();
+    pending.truncate(pending.len().saturating_sub(restored));
+    check_undo(&pending)?;
+
+    let mut undone = Undone {
+        run: run.clone(),
+        timestamp: changes[0].timestamp.clone(),
+        restored: Vec::new(),
+        error: None,
+    };
+    // replay the run in reverse, recording every step so an undo that stops halfway can be resumed
+    journal.run = run;
+    for entry in pending.iter().rev() {
+        let restored = undo_entry(entry)
+            .map_err(|e| io::Error::new(e.kind(), format!("can't restore {}: {}", entry.path.display(), e)))
+            .and_then(|()| journal.record(EntryKind::Restored, &entry.path, &entry.target, entry.size, entry.hash.as_ref()));
+        if let Err(e) = restored {
+            undone.error = Some(e);
+            return Ok(undone);
+        }
+        undone.restored.push((*entry).clone());
+    }
+
+    // mark the run as undone, so it isn't replayed twice
+    undone.error = journal.record(EntryKind::Undone, Path::new("/"), Path::new("/"), 0, None).err();
+    Ok(undone)
+}
+
+// restore the file changed by a single entry
+fn undo_entry(entry: &Entry) -> io::Result<()> {
+    match entry.kind {
+        EntryKind::Rename => {
+            verify_hash(&entry.target, entry.hash.as_ref())?;
+            sys::rename_noreplace(&entry.target, &entry.path)
+        }
+        EntryKind::Remove => {
+            verify_hash(&entry.target, entry.hash.as_ref())?;
+            // a removed symlink is restored as a link, not a copy of the file it points to
+            match std::fs::symlink_metadata(&entry.target) {
+                Ok(m) if m.is_symlink() => std::os::unix::fs::symlink(link_text(&entry.target, &entry.path)?, &entry.path),
+                _ => std::fs::copy(&entry.target, &entry.path).map(|_| ()),
+            }
+        }
+        EntryKind::Trash => {
+            sys::rename_noreplace(&entry.target, &entry.path)?;
+            if let Some(info) = trash::info_file(&entry.target) {
+                let _ = std::fs::remove_file(info);
+            }
+            Ok(())
+        }
+        EntryKind::HardLink | EntryKind::Symlink => link::copy(&entry.target, &entry.path),
+        EntryKind::Restored | EntryKind::Undone => Ok(()),
+    }
+}
+
+// the target of a new link at path that points where the link kept does (the removed link was
+// only grouped with kept because both resolve to the same path, but their text can differ)
+fn link_text(kept: &Path, path: &Path) -> io::Result<PathBuf> {
+    let resolved = scan::link_target(kept)?;
+    if std::fs::read_link(kept)?.is_absolute() {
+        return Ok(resolved);
+    }
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => std::env::current_dir()?,
+    };
+    Ok(link::relative_path(&directory, &resolved))
+}
+
+// refuse to undo a run if any of its original paths were reused since
+fn check_undo(changes: &[&Entry]) -> io::Result<()> {
+    // paths freed and occupied by the already replayed entries
+    let mut freed: HashSet<&Path> = HashSet::new();
+    let mut occupied: HashSet<&Path> = HashSet::new();
+    let mut conflicts = Vec::new();
+
+    for entry in changes.iter().rev() {
+        let exists = |path: &Path| occupied.contains(path) || (!freed.contains(path) && path.symlink_metadata().is_ok());
+        match entry.kind {
+            EntryKind::Restored | EntryKind::Undone => continue,
+            EntryKind::Rename | EntryKind::Trash | EntryKind::HardLink | EntryKind::Symlink if !exists(&entry.target) => {
+                conflicts.push(format!("{} no longer exists", entry.target.display()));
+            }
+            EntryKind::Remove if !exists(&entry.target) => {
+                conflicts.push(format!("{} (to restore {} from) no longer exists", entry.target.display(), entry.path.display()));
+            }
+            // (the size of a trashed symbolic link is recorded as 0)
+            EntryKind::Trash if entry.target.symlink_metadata().is_ok_and(|m| !m.is_symlink() && m.len() != entry.size) => {
+                conflicts.push(format!("{} has been modified since", entry.target.display()));
+            }
+            EntryKind::Rename if !occupied.contains(entry.target.as_path()) && verify_hash(&entry.target, entry.hash.as_ref()).is_err() => {
+                conflicts.push(format!("{} has been modified since", entry.target.display()));
+            }
+            _ => {}
+        }
+        if matches!(entry.kind, EntryKind::HardLink | EntryKind::Symlink) {
+            // the link has to still point to the same file
+            let linked = match entry.kind {
+                EntryKind::HardLink => same_file(&entry.path, &entry.target),
+                _ => links_to(&entry.path, &entry.target),
+            };
+            if !occupied.contains(entry.path.as_path()) && !linked {
+                conflicts.push(format!("{} has been reused", entry.path.display()));
+            }
+            continue;
+        }
+        if exists(&entry.path) {
+            conflicts.push(format!("{} has been reused", entry.path.display()));
+        }
+
+        if entry.kind != EntryKind::Remove {
+            freed.insert(&entry.target);
+            occupied.remove(entry.target.as_path());
+        }
+        occupied.insert(&entry.path);
+        freed.remove(entry.path.as_path());
+    }
+
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("refusing to undo:\n    {}", conflicts.join("\n    "))))
+    }
+}
+
+// whether both paths are hard links to the same file
+fn same_file(path1: &Path, path2: &Path) -> bool {
+    match (path1.symlink_metadata(), path2.symlink_metadata()) {
+        (Ok(m1), Ok(m2)) => m1.dev() == m2.dev() && m1.ino() == m2.ino(),
+        _ => false,
+    }
+}
+
+// whether path is a symbolic link resolving to target
+fn links_to(path: &Path, target: &Path) -> bool {
+    let is_symlink = path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink());
+    match (path.metadata(), target.metadata()) {
+        (Ok(m1), Ok(m2)) => is_symlink && m1.dev() == m2.dev() && m1.ino() == m2.ino(),
+        _ => false,
+    }
+}
+
+// make sure a file still has the contents recorded in the journal
+fn verify_hash(path: &Path, expected: Option<&Digest>) -> io::Result<()> {
+    match expected {
+        Some(expected) if hash::hash_file(path)? != *expected => Err(io::Error::other(format!(
+            "{} has been modified since, refusing to restore it",
+            path.display()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// escape the bytes that would break the line format
+pub(crate) fn escape(path: &Path) -> Vec<u8> {
+    let mut escaped = Vec::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b == b'%' || b < 0x20 || b == 0x7f {
+            escaped.extend_from_slice(format!("%{:02X}", b).as_bytes());
+        } else {
+            escaped.push(b);
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape(bytes: &[u8]) -> PathBuf {
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        if bytes[i] == b'%'
+            && let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok())
+        {
+            unescaped.push(b);
+            i += 3;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+    PathBuf::from(OsString::from_vec(unescaped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"/plain/path.txt", b"/plain/path.txt"),
+            (b"/with\ttab\nand newline", b"/with%09tab%0Aand newline"),
+            (b"/100%", b"/100%25"),
+            (b"/del\x7f", b"/del%7F"),
+            // bytes that aren't valid utf-8 stay as they are
+            (b"/caf\xe9", b"/caf\xe9"),
+        ];
+        for &(path, escaped) in cases {
+            let path = Path::new(std::ffi::OsStr::from_bytes(path));
+            assert_eq!(escape(path), escaped, "{:?}", path);
+            assert_eq!(unescape(escaped), path);
+        }
+    }
+
+    #[test]
+    fn unescaping_is_lenient() {
+        // a % that doesn't start an escape is kept
+        assert_eq!(unescape(b"/50%"), Path::new("/50%"));
+        assert_eq!(unescape(b"/%zz"), Path::new("/%zz"));
+        assert_eq!(unescape(b"/%4"), Path::new("/%4"));
+        assert_eq!(unescape(b"/%4a"), Path::new("/J"));
+    }
+
+    #[test]
+    fn entries() {
+        let line = b"1700000000-42\t2023-11-14T22:13:20\trename\t/a%09b (1).txt\t/a%09b.txt\t12\t-";
+        let entry = parse_entry(line).unwrap();
+        assert_eq!(entry.run, "1700000000-42");
+        assert_eq!(entry.kind, EntryKind::Rename);
+        assert_eq!(entry.path, Path::new("/a\tb (1).txt"));
+        assert_eq!(entry.target, Path::new("/a\tb.txt"));
+        assert_eq!(entry.size, 12);
+        assert_eq!(entry.hash, None);
+
+        let hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let line = format!("run\tnow\tremove\t/a\t/b\t3\t{}", hash);
+        assert_eq!(parse_entry(line.as_bytes()).unwrap().hash, hash::from_hex(hash));
+
+        for malformed in [&b"run\tnow\tremove\t/a\t/b\t3"[..], b"run\tnow\tunknown\t/a\t/b\t3\t-", b"run\tnow\tremove\t/a\t/b\tx\t-", b"run\tnow\tremove\t/a\t/b\t3\tabc"] {
+            assert!(parse_entry(malformed).is_none(), "{:?}", String::from_utf8_lossy(malformed));
+        }
+    }
+
+    #[test]
+    fn restoring_removed_symlinks() {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-undo-links-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("t"), "target").unwrap();
+        // the kept link and the removed one were at different depths
+        std::os::unix::fs::symlink("../t", dir.join("a/kept")).unwrap();
+        let entry = Entry {
+            run: "run".to_string(),
+            timestamp: "now".to_string(),
+            kind: EntryKind::Remove,
+            path: dir.join("a/b/removed"),
+            target: dir.join("a/kept"),
+            size: 0,
+            hash: None,
+        };
+        undo_entry(&entry).unwrap();
+        assert_eq!(std::fs::read_link(&entry.path).unwrap(), Path::new("../../t"));
+        assert_eq!(std::fs::read_to_string(&entry.path).unwrap(), "target");
+
+        // absolute links stay absolute
+        std::fs::remove_file(&entry.path).unwrap();
+        std::fs::remove_file(&entry.target).unwrap();
+        std::os::unix::fs::symlink(dir.join("a/../t"), &entry.target).unwrap();
+        undo_entry(&entry).unwrap();
+        assert_eq!(std::fs::read_link(&entry.path).unwrap(), dir.join("t"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resuming_a_partial_undo() {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-undo-resume-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // the second rename of the run was undone before the undo stopped
+        std::fs::write(dir.join("x.txt"), "x").unwrap();
+        std::fs::write(dir.join("y (1).txt"), "y").unwrap();
+        let line = |kind: &str, name: &str| {
+            format!("run\tnow\t{}\t{}\t{}\t1\t-\n", kind, dir.join(format!("{} (1).txt", name)).display(), dir.join(format!("{}.txt", name)).display())
+        };
+        let path = dir.join("journal");
+        std::fs::write(&path, [line("rename", "x"), line("rename", "y"), line("restored", "y")].concat()).unwrap();
+
+        let mut journal = Journal::new(path.clone());
+        let undone = undo(&mut journal, None).unwrap();
+        assert!(undone.error.is_none());
+        assert_eq!(undone.restored.len(), 1);
+        assert_eq!(undone.restored[0].path, dir.join("x (1).txt"));
+        assert!(dir.join("x (1).txt").exists() && !dir.join("x.txt").exists());
+        let kinds: Vec<EntryKind> = read_entries(&path).unwrap().iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, [EntryKind::Rename, EntryKind::Rename, EntryKind::Restored, EntryKind::Restored, EntryKind::Undone]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+// choosing which file of a duplicate group is kept
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::normalize::Normalizer;
+use crate::scan::{compare_file_times, FileData, TimeSource};
+
+// a single criterion for preferring one file over another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepPolicy {
+    // the most recently created (or modified) file
+    Newest,
+    // the least recently created (or modified) file
+    Oldest,
+    Largest,
+    ShortestPath,
+    DeepestPath,
+    // the file without a " (n)" counter in its name
+    UnnumberedName,
+    // the file in the first matching preferred directory
+    PathPriority,
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newest" => Ok(KeepPolicy::Newest),
+            "oldest" => Ok(KeepPolicy::Oldest),
+            "largest" => Ok(KeepPolicy::Largest),
+            "shortest-path" => Ok(KeepPolicy::ShortestPath),
+            "deepest-path" => Ok(KeepPolicy::DeepestPath),
+            "unnumbered-name" => Ok(KeepPolicy::UnnumberedName),
+            "path-priority" => Ok(KeepPolicy::PathPriority),
+            _ => Err(format!(
+                "unknown keep policy '{}' (expected newest, oldest, largest, shortest-path, deepest-path, unnumbered-name or path-priority)",
+                s
+            )),
+        }
+    }
+}
+
+// the policies in order of importance, later policies only break ties of earlier ones
+#[derive(Debug, Clone)]
+pub struct KeepOrder {
+    pub policies: Vec<KeepPolicy>,
+    // the preferred directories for path-priority, most preferred first
+    pub preferred_dirs: Vec<PathBuf>,
+    // recognises numbered names for unnumbered-name
+    pub normalizer: Normalizer,
+}
+
+impl KeepOrder {
+    pub fn new(policies: Vec<KeepPolicy>, preferred_dirs: Vec<PathBuf>, normalizer: Normalizer) -> Self {
+        let preferred_dirs = preferred_dirs.iter().map(|d| absolute(d)).collect();
+        KeepOrder { policies, preferred_dirs, normalizer }
+    }
+
+    // orders the file to keep first
+    pub fn compare(&self, fd1: &FileData, fd2: &FileData) -> Ordering {
+        self.decide(fd1, fd2).0
+    }
+
+    // describes why the first file is kept rather than the second one (e.g. "newer by birth time")
+    pub fn explain(&self, kept: &FileData, other: &FileData) -> String {
+        if kept.reference && !other.reference {
+            return "in a reference directory".to_string();
+        }
+        match self.decide(kept, other) {
+            (_, Some(policy), Some(source)) => format!("{} by {}", policy_reason(policy), source),
+            (_, Some(policy), None) => policy_reason(policy).to_string(),
+            (_, None, _) => "first by path".to_string(),
+        }
+    }
+
+    // the ordering of two files together with the policy (and timestamp) that decided it
+    fn decide(&self, fd1: &FileData, fd2: &FileData) -> (Ordering, Option<KeepPolicy>, Option<TimeSource>) {
+        // files in reference directories are always kept, whatever the policies say
+        if fd1.reference != fd2.reference {
+            return (fd2.reference.cmp(&fd1.reference), None, None);
+        }
+        for policy in &self.policies {
+            let (ordering, source) = self.compare_by(*policy, fd1, fd2);
+            if ordering != Ordering::Equal {
+                return (ordering, Some(*policy), source);
+            }
+        }
+        // fall back to the path, so the choice doesn't depend on the walk order
+        (fd1.filepath.cmp(&fd2.filepath), None, None)
+    }
+
+    fn compare_by(&self, policy: KeepPolicy, fd1: &FileData, fd2: &FileData) -> (Ordering, Option<TimeSource>) {
+        let ordering = match policy {
+            KeepPolicy::Newest => {
+                let (ordering, source) = compare_file_times(fd2, fd1);
+                return (ordering, Some(source));
+            }
+            KeepPolicy::Oldest => {
+                let (ordering, source) = compare_file_times(fd1, fd2);
+                return (ordering, Some(source));
+            }
+            KeepPolicy::Largest => fd2.metadata.len().cmp(&fd1.metadata.len()),
+            KeepPolicy::ShortestPath => path_len(&fd1.filepath).cmp(&path_len(&fd2.filepath)),
+            KeepPolicy::DeepestPath => depth(&fd2.filepath).cmp(&depth(&fd1.filepath)),
+            KeepPolicy::UnnumberedName => self.is_numbered(&fd1.filepath).cmp(&self.is_numbered(&fd2.filepath)),
+            KeepPolicy::PathPriority => self.priority(&fd1.filepath).cmp(&self.priority(&fd2.filepath)),
+        };
+        (ordering, None)
+    }
+
+    fn is_numbered(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|filename| self.normalizer.is_numbered(filename))
+    }
+
+    // the index of the first preferred directory containing the path
+    fn priority(&self, path: &Path) -> usize {
+        let path = absolute(path);
+        self.preferred_dirs.iter()
+            .position(|dir| path.starts_with(dir))
+            .unwrap_or(usize::MAX)
+    }
+}
+
+// how a policy prefers the kept file
+fn policy_reason(policy: KeepPolicy) -> &'static str {
+    match policy {
+        KeepPolicy::Newest => "newer",
+        KeepPolicy::Oldest => "older",
+        KeepPolicy::Largest => "larger",
+        KeepPolicy::ShortestPath => "shorter path",
+        KeepPolicy::DeepestPath => "deeper path",
+        KeepPolicy::UnnumberedName => "unnumbered name",
+        KeepPolicy::PathPriority => "preferred directory",
+    }
+}
+
+fn path_len(path: &Path) -> usize {
+    path.as_os_str().len()
+}
+
+fn depth(path: &Path) -> usize {
+    absolute(path).components().count()
+}
+
+// the absolute form of a path (falling back to the path itself)
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::normalize::Pattern;
+
+    // files created in order, each one a minute newer than the previous one (so birth and
+    // modification times agree)
+    fn files(dir: &Path, names: &[(&str, usize)]) -> Vec<FileData> {
+        let start = SystemTime::now() - Duration::from_secs(3600);
+        names.iter().enumerate().map(|(i, &(name, size))| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let file = std::fs::File::create(&path).unwrap();
+            file.set_len(size as u64).unwrap();
+            file.set_modified(start + Duration::from_secs(60 * i as u64)).unwrap();
+            let metadata = path.symlink_metadata().unwrap();
+            FileData { filepath: path, root: dir.to_path_buf(), reference: false, metadata, hash: None }
+        }).collect()
+    }
+
+    #[test]
+    fn policies() {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-keep-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let normalizer = Normalizer::new(&[Pattern::Numbered], Vec::new(), &[]);
+        let group = files(&dir, &[
+            ("b/photo (1).jpg", 10),
+            ("a/deep/er/photo.jpg", 20),
+            ("photo (2).jpg", 20),
+            ("c/photo (3).jpg", 5),
+        ]);
+
+        // the policies, the preferred directories, the kept file and why it was kept over the next one
+        let cases: &[(&str, &[&str], &str, &str)] = &[
+            ("newest", &[], "c/photo (3).jpg", "newer by"),
+            ("oldest", &[], "b/photo (1).jpg", "older by"),
+            ("largest", &[], "a/deep/er/photo.jpg", "first by path"),
+            ("unnumbered-name", &[], "a/deep/er/photo.jpg", "unnumbered name"),
+            ("shortest-path", &[], "photo (2).jpg", "shorter path"),
+            ("deepest-path", &[], "a/deep/er/photo.jpg", "deeper path"),
+            ("path-priority", &["c", "b"], "c/photo (3).jpg", "preferred directory"),
+            ("path-priority,newest", &["x", "b"], "b/photo (1).jpg", "preferred directory"),
+            // later policies only break the ties of earlier ones
+            ("largest,newest", &[], "photo (2).jpg", "newer by"),
+            ("largest,oldest", &[], "a/deep/er/photo.jpg", "older by"),
+            ("largest,shortest-path", &[], "photo (2).jpg", "shorter path"),
+            // files in none of the preferred directories tie
+            ("path-priority,oldest", &["x"], "b/photo (1).jpg", "older by"),
+        ];
+        for &(policies, preferred, expected, reason) in cases {
+            let policies = policies.split(',').map(|p| p.parse().unwrap()).collect();
+            let order = KeepOrder::new(policies, preferred.iter().map(|d| dir.join(d)).collect(), normalizer.clone());
+            let mut sorted: Vec<&FileData> = group.iter().collect();
+            sorted.sort_by(|a, b| order.compare(a, b));
+            assert_eq!(sorted[0].filepath, dir.join(expected), "{:?}", order.policies);
+            let explained = order.explain(sorted[0], sorted[1]);
+            assert!(explained.starts_with(reason), "{:?}: {}", order.policies, explained);
+        }
+
+        // files in reference directories are kept whatever the policies say
+        let mut reference = files(&dir, &[("ref/photo (9).jpg", 1)]);
+        reference[0].reference = true;
+        let order = KeepOrder::new(vec![KeepPolicy::Largest], Vec::new(), normalizer);
+        assert_eq!(order.compare(&reference[0], &group[1]), Ordering::Less);
+        assert_eq!(order.explain(&reference[0], &group[1]), "in a reference directory");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
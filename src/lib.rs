@@ -0,0 +1,35 @@
+// finding the files that are copies of each other by their names ("name (1).ext") and
+// contents, and removing, trashing or linking them
+//
+// the uniquer binary is a thin front end over this library:
+//
+//     let normalizer = Normalizer::new(&[Pattern::Numbered], Vec::new(), &[]);
+//     let scanner = Scanner::new(normalizer.clone()).byte_compare(true);
+//     let mut errors = Vec::new();
+//     let groups = scanner.scan(&[PathBuf::from("photos")], &mut errors)?;
+//     let keep_order = KeepOrder::new(vec![KeepPolicy::Newest], Vec::new(), normalizer.clone());
+//     let plan = plan_deletions(groups.duplicates, &Trash, &normalizer, &keep_order, true, OnConflict::KeepNumbered);
+//     let applied = plan.apply(&mut Journal::new(journal::default_path()?), false)?;
+//
+// nothing is printed, the reasons for keeping or skipping files end up in plan.notes and
+// applied.notes and the per-file errors in applied.errors
+pub mod config;
+pub mod error;
+pub mod filter;
+pub mod hash;
+pub mod journal;
+pub mod keep;
+pub mod link;
+pub mod normalize;
+pub mod plan;
+pub mod results;
+pub mod scan;
+pub mod trash;
+mod sys;
+mod unicode;
+mod unicode_tables;
+mod xdg;
+
+pub use crate::error::{Error, Result};
+pub use crate::plan::{Action, Applied, Link, Note, OnConflict, Operation, Plan, Remove, Trash};
+pub use crate::scan::{DuplicateGroup, FileData, Scanner, VerifiedGroups};
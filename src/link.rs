@@ -0,0 +1,182 @@
+// replacing duplicates with links to the kept file
+use std::fs::{File, Metadata, OpenOptions};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use crate::sys;
+
+// the kind of link duplicates are replaced with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Hard,
+    // copy-on-write clones sharing their extents (btrfs, xfs)
+    Reflink,
+    // symbolic links, either relative to the link or absolute
+    Symlink { relative: bool },
+}
+
+impl FromStr for LinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hard" => Ok(LinkKind::Hard),
+            "reflink" => Ok(LinkKind::Reflink),
+            "symlink" | "symlink:relative" => Ok(LinkKind::Symlink { relative: true }),
+            "symlink:absolute" => Ok(LinkKind::Symlink { relative: false }),
+            _ => Err(format!(
+                "unknown link kind '{}' (expected 'hard', 'reflink' or 'symlink[:relative|absolute]')",
+                s
+            )),
+        }
+    }
+}
+
+// atomically replace path with a hard link to kept
+pub fn hard_link(kept: &Path, path: &Path) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    std::fs::hard_link(kept, &temp).map_err(|e| {
+        if e.kind() == io::ErrorKind::CrossesDevices {
+            io::Error::new(e.kind(), format!("can't hard link to {}, it is on a different filesystem", kept.display()))
+        } else {
+            e
+        }
+    })?;
+    replace(&temp, path)
+}
+
+// let the filesystem share the extents of path with kept, keeping both files and their metadata
+// (returns the number of bytes deduplicated)
+pub fn reflink(kept: &Path, path: &Path) -> io::Result<u64> {
+    let src = File::open(kept)?;
+    let len = src.metadata()?.len();
+    // the owner can share the extents of a file opened read-only (so read-only files work too),
+    // anyone else needs it opened for writing. its contents are left untouched either way
+    let dest = File::open(path)?;
+    match sys::dedupe_range(&src, &dest, len) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            let dest = OpenOptions::new().write(true).open(path)?;
+            sys::dedupe_range(&src, &dest, len)
+        }
+        result => result,
+    }
+}
+
+// atomically replace path with a symbolic link to kept, making sure the link resolves to kept
+pub fn symlink(kept: &Path, path: &Path, relative: bool) -> io::Result<()> {
+    let kept_metadata = kept.metadata()?;
+    let kept = kept.canonicalize()?;
+    let target = if relative {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+            _ => std::env::current_dir()?,
+        };
+        relative_path(&directory, &kept)
+    } else {
+        kept.clone()
+    };
+
+    // the temporary link lives in the same directory, so it resolves the same way as the final one
+    let temp = temp_path(path)?;
+    std::os::unix::fs::symlink(&target, &temp)?;
+    if !resolves_to(&temp, &kept_metadata) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(io::Error::other(format!("a link to {} doesn't resolve to {}", target.display(), kept.display())));
+    }
+    replace(&temp, path)
+}
+
+// whether the link resolves to the file with the given metadata
+fn resolves_to(link: &Path, metadata: &Metadata) -> bool {
+    link.metadata().is_ok_and(|m| m.dev() == metadata.dev() && m.ino() == metadata.ino())
+}
+
+// the path of to relative to the directory from (both absolute and canonical)
+pub(crate) fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+// atomically replace path with an independent copy of source
+pub fn copy(source: &Path, path: &Path) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    std::fs::copy(source, &temp)?;
+    replace(&temp, path)
+}
+
+// move the temporary file over the path, cleaning up if that fails
+fn replace(temp: &Path, path: &Path) -> io::Result<()> {
+    std::fs::rename(temp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(temp);
+    })
+}
+
+// a hidden temporary name next to the path (renames are only atomic within a directory)
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let filename = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temp = std::ffi::OsString::from(".");
+    temp.push(filename);
+    temp.push(format!(".uniquer-{}", std::process::id()));
+    Ok(path.with_file_name(temp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths() {
+        let cases: &[(&str, &str, &str)] = &[
+            ("/a/b", "/a/b/c.txt", "c.txt"),
+            ("/a/b", "/a/c.txt", "../c.txt"),
+            ("/a/b/c", "/a/x/y.txt", "../../x/y.txt"),
+            ("/a", "/b/c.txt", "../b/c.txt"),
+            ("/", "/a/b.txt", "a/b.txt"),
+            ("/a/b", "/c.txt", "../../c.txt"),
+        ];
+        for &(from, to, expected) in cases {
+            assert_eq!(relative_path(Path::new(from), Path::new(to)), Path::new(expected), "{} to {}", from, to);
+        }
+    }
+
+    #[test]
+    fn symlinks() {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-symlinks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("kept.txt"), "same").unwrap();
+        std::fs::write(dir.join("other.txt"), "same").unwrap();
+        std::fs::write(dir.join("a/b/copy.txt"), "same").unwrap();
+
+        symlink(&dir.join("kept.txt"), &dir.join("a/b/copy.txt"), true).unwrap();
+        assert_eq!(std::fs::read_link(dir.join("a/b/copy.txt")).unwrap(), Path::new("../../kept.txt"));
+        symlink(&dir.join("kept.txt"), &dir.join("a/b/copy.txt"), false).unwrap();
+        assert_eq!(std::fs::read_link(dir.join("a/b/copy.txt")).unwrap(), dir.canonicalize().unwrap().join("kept.txt"));
+
+        // links are only kept if they resolve to the kept file itself
+        let kept = dir.join("kept.txt").metadata().unwrap();
+        std::os::unix::fs::symlink("other.txt", dir.join("wrong")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", dir.join("dangling")).unwrap();
+        std::os::unix::fs::symlink("kept.txt", dir.join("right")).unwrap();
+        assert!(!resolves_to(&dir.join("wrong"), &kept));
+        assert!(!resolves_to(&dir.join("dangling"), &kept));
+        assert!(resolves_to(&dir.join("right"), &kept));
+
+        // a duplicate stays as it is if the kept file is gone
+        std::fs::write(dir.join("copy.txt"), "same").unwrap();
+        assert!(symlink(&dir.join("missing.txt"), &dir.join("copy.txt"), true).is_err());
+        assert!(!dir.join("copy.txt").symlink_metadata().unwrap().is_symlink());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,131 +1,476 @@
-use clap::{Parser, Subcommand};
-use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::fs::Metadata;
-use std::path::PathBuf;
-use regex::Regex;
-use walkdir::{DirEntry, WalkDir};
+// the command line front end of the uniquer library
+use clap::{Args, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use uniquer::error::{EXIT_FATAL, EXIT_HANDLED, EXIT_NO_DUPLICATES, EXIT_PARTIAL_FAILURE};
+use uniquer::filter::{Filter, PathRule};
+use uniquer::journal::{self, EntryKind, Journal};
+use uniquer::keep::{KeepOrder, KeepPolicy};
+use uniquer::link::LinkKind;
+use uniquer::normalize::{self, Normalizer, Pattern};
+use uniquer::plan::{format_size, plan_deletions, plan_renames, Note, Operation, Plan};
+use uniquer::{config, results, scan, Action, DuplicateGroup, Error, Link, OnConflict, Remove, Scanner, Trash};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
-struct CLI {
-    /// the absolute path to the directory
-    directory_path: String,
-}
-
-// the struct for comparing the files for checking duplicates
-#[derive(Debug)]
-struct FileData {
-    pub filepath: PathBuf,
-    pub metadata: Metadata,
-}
-
-// normalizes files to their basename (without enumrations)
-fn normalize_file(filename: &str) -> String {
-    // regular expression of format: "filename (number).extension (optional)"
-    let regex = Regex::new(r"^(?P<base>.+?)\s*\(\d+\)(?P<ext>\.\w+)?$").unwrap();
-    if let Some(capture) = regex.captures(filename) {
-        // transform the basename capture to string
-        let basename = capture.name("base").unwrap().as_str();
-        // handle extension cases as they are optional
-        let extension = capture.name("ext").map_or("", |ex| ex.as_str());
-        format!("{}{}", basename, extension)
-    } else {
-        filename.to_string()
-    }
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// the journal recording every change (defaults to ~/.local/state/uniquer/journal)
+    #[arg(long, global = true)]
+    journal: Option<PathBuf>,
 }
 
-// filter function for ignoring hidden files
-fn is_hidden(dir_entry: &DirEntry) -> bool {
-    dir_entry.file_name()
-        .to_str()
-        .map(|s| s.starts_with("."))
-        .unwrap_or(false)
+#[derive(Subcommand)]
+enum Command {
+    /// find the duplicates in a directory tree and save them for review
+    Scan {
+        /// the directories to scan (duplicates are found across all of them)
+        #[arg(required = true)]
+        directories: Vec<PathBuf>,
+
+        /// where to save the results (defaults to ~/.local/state/uniquer/scan)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// show the duplicates of a saved scan
+    Report {
+        /// the saved scan (defaults to ~/.local/state/uniquer/scan)
+        file: Option<PathBuf>,
+    },
+    /// delete, trash or link the duplicates of a saved scan or a freshly scanned directory tree
+    Clean {
+        /// the directories to scan (duplicates are found across all of them)
+        #[arg(required_unless_present = "from", conflicts_with = "from")]
+        directories: Vec<PathBuf>,
+
+        /// clean up the duplicates of a saved scan instead (defaults to ~/.local/state/uniquer/scan),
+        /// files changed since the scan are skipped and names are recognised with the settings of the scan
+        #[arg(long, value_name = "FILE", num_args = 0..=1, conflicts_with = "ScanArgs")]
+        from: Option<Option<PathBuf>>,
+
+        #[command(flatten)]
+        scan: ScanArgs,
+
+        #[command(flatten)]
+        clean: CleanArgs,
+    },
+    /// restore the files changed by a previous run
+    Undo {
+        /// the run to undo (defaults to the last run that wasn't undone yet)
+        #[arg(long)]
+        run: Option<String>,
+    },
 }
 
-// compare file data based on their metadata
-fn compare_file_data(fd1: &FileData, fd2: &FileData) -> Ordering {
-    // creation times of file data
-    let c1 = fd1.metadata.created().unwrap();
-    let c2 = fd2.metadata.created().unwrap();
+// how files are recognised as copies of each other
+#[derive(Args)]
+struct ScanArgs {
+    /// the naming patterns of copies to recognise (numbered, numbered-nospace, windows-copy,
+    /// macos-copy, underscore, dash, copy-of)
+    #[arg(long, value_name = "PATTERN", value_delimiter = ',', default_value = "numbered,numbered-nospace")]
+    pattern: Vec<Pattern>,
+
+    /// an extension made of several parts, in addition to the built-in ones like .tar.gz
+    #[arg(long = "compound-ext", value_name = "EXT", value_parser = normalize::check_extension)]
+    compound_extensions: Vec<String>,
+
+    /// group names that only differ in case (e.g. "Photo.JPG" and "photo (1).jpg")
+    #[arg(long)]
+    ignore_case: bool,
 
-    // modified times of file data
-    let m1 = fd1.metadata.modified().unwrap();
-    let m2 = fd2.metadata.modified().unwrap();
+    /// group names that only differ in their unicode normalization (e.g. "Café" in nfc and nfd,
+    /// as copied from macos)
+    #[arg(long)]
+    unicode_normalize: bool,
 
+    /// the configuration file with user defined patterns (defaults to ~/.config/uniquer/config.toml)
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    // sort the paths based on created date (oldest is the first element)
-    // ordering is important here (Ascending in this case)
-    c1.cmp(&c2)
-        // if creation time is equal, sort on modified time
-        .then(m1.cmp(&m2))
+    /// group files with the same name across the whole tree instead of per directory
+    #[arg(long)]
+    cross_directory: bool,
+
+    /// a directory whose files are never deleted or renamed, copies of them in the scanned
+    /// directories are removed instead (combine with --cross-directory when the layouts differ)
+    #[arg(long = "reference", value_name = "DIR")]
+    references: Vec<PathBuf>,
+
+    /// only scan the files matching a gitignore-style glob, relative to the root (e.g. "*.jpg"
+    /// or "photos/**/*.png", a glob without a "/" matches the name at any depth)
+    #[arg(long, value_name = "GLOB", value_parser = PathRule::glob)]
+    include: Vec<PathRule>,
+
+    /// skip the files and directories matching a gitignore-style glob, relative to the root
+    /// (a trailing "/" only matches directories, nothing inside them is scanned)
+    #[arg(long, value_name = "GLOB", value_parser = PathRule::glob)]
+    exclude: Vec<PathRule>,
+
+    /// skip the directories matching a gitignore-style glob with everything inside them
+    /// (e.g. "node_modules" or "/build")
+    #[arg(long, value_name = "GLOB", value_parser = PathRule::dir_glob)]
+    exclude_dir: Vec<PathRule>,
+
+    /// like --include, with a regex searched for in the path relative to the root
+    #[arg(long, value_name = "REGEX", value_parser = PathRule::regex)]
+    include_regex: Vec<PathRule>,
+
+    /// like --exclude, with a regex searched for in the path relative to the root
+    #[arg(long, value_name = "REGEX", value_parser = PathRule::regex)]
+    exclude_regex: Vec<PathRule>,
+
+    /// also group symbolic links, which are duplicates if they point to the same target
+    /// (links are never followed, directories and special files are always skipped)
+    #[arg(long)]
+    include_symlinks: bool,
+
+    /// compare files byte for byte after their hashes matched
+    #[arg(long)]
+    byte_compare: bool,
 }
 
-// group all duplicate files into a group
-fn group_duplicates(directory: &str) -> HashMap<String, Vec<FileData>> {
-    let mut duplicate_map: HashMap<String, Vec<FileData>> = HashMap::new();
+// what happens to the duplicates
+#[derive(Args)]
+struct CleanArgs {
+    /// only print what would be deleted and renamed
+    #[arg(long)]
+    dry_run: bool,
+
+    /// move duplicates to the trash instead of deleting them
+    #[arg(long)]
+    trash: bool,
+
+    /// replace duplicates with links to the kept file instead of deleting them
+    /// (hard, reflink, symlink[:relative|absolute])
+    #[arg(long, value_name = "KIND", conflicts_with = "trash")]
+    link: Option<LinkKind>,
 
-    let walker = WalkDir::new(directory).into_iter();
-    for entry in walker.filter_entry(|e| !is_hidden(e)) {
-        let e = entry.unwrap();
-        // add all same entries into hash map
-        let basename = normalize_file(e.path().file_name().unwrap().to_str().unwrap());
-        //println!("{:?}", basename);
+    /// delete the duplicates but never rename the kept file
+    #[arg(long)]
+    no_rename: bool,
 
-        // check if basename is already in hash map
-        if duplicate_map.contains_key(&basename) {
-            // if it does add the path to the hash map
-            let pair = duplicate_map.get_mut(&basename).unwrap();
-            let file_data = FileData {
-                filepath: e.path().to_path_buf(),
-                metadata: e.path().metadata().unwrap(),
-            };
+    /// only strip the copy markers from files that have no unnumbered sibling, deleting nothing
+    #[arg(long, conflicts_with_all = ["no_rename", "trash", "link", "from"])]
+    rename_only: bool,
+
+    /// what to do when the name the kept file would be renamed to is taken by another file
+    /// (skip, keep-numbered, next-free)
+    #[arg(long, value_name = "STRATEGY", default_value = "keep-numbered")]
+    on_conflict: OnConflict,
+
+    /// which file of a group to keep, later policies break ties (newest, oldest, largest,
+    /// shortest-path, deepest-path, unnumbered-name, path-priority)
+    #[arg(long, value_name = "POLICY", value_delimiter = ',', default_value = "newest")]
+    keep: Vec<KeepPolicy>,
+
+    /// a preferred directory for the path-priority policy (most preferred first)
+    #[arg(long, value_name = "DIR")]
+    prefer: Vec<PathBuf>,
+
+    /// stop at the first error instead of skipping the file and continuing (nothing is changed
+    /// if it happens while scanning)
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// explain which file of each group is kept and why
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+// report the files which only matched by name (with the root they were found below, if there are several)
+fn print_collisions(collisions: &[DuplicateGroup], show_roots: bool) {
+    if collisions.is_empty() {
+        return;
+    }
+    println!("name collisions, different content:");
+    for group in collisions {
+        println!("    {}", group.key.display());
+        for f in &group.files {
+            print_file(&f.filepath, &f.root, None, show_roots, f.reference);
+        }
+    }
+}
+
+// print a file of a group, optionally with its size and root
+fn print_file(path: &Path, root: &Path, size: Option<u64>, show_root: bool, reference: bool) {
+    let mut line = format!("        {}", path.display());
+    if let Some(size) = size {
+        line.push_str(&format!(" ({})", format_size(size)));
+    }
+    if show_root {
+        line.push_str(&format!(" [{}]", root.display()));
+    }
+    if reference {
+        line.push_str(" [reference]");
+    }
+    println!("{}", line);
+}
+
+// print the plan without touching the filesystem
+fn print_plan(plan: &Plan) {
+    let mut removed = 0;
+    let mut linked = 0;
+    for op in plan.operations() {
+        match op {
+            Operation::Remove { path, size, .. } => {
+                removed += 1;
+                println!("delete {} ({})", path.display(), format_size(*size));
+            }
+            Operation::Trash { path, size, .. } => {
+                removed += 1;
+                println!("trash {} ({})", path.display(), format_size(*size));
+            }
+            Operation::HardLink { path, size, kept, .. } => {
+                linked += 1;
+                println!("hard link {} -> {} ({})", path.display(), kept.display(), format_size(*size));
+            }
+            Operation::Reflink { path, size, kept } => {
+                linked += 1;
+                println!("reflink {} -> {} ({})", path.display(), kept.display(), format_size(*size));
+            }
+            Operation::Symlink { path, size, kept, relative, .. } => {
+                linked += 1;
+                let style = if *relative { "relative" } else { "absolute" };
+                println!("{} symlink {} -> {} ({})", style, path.display(), kept.display(), format_size(*size));
+            }
+            Operation::Rename { from, to, .. } => {
+                println!("rename {} -> {}", from.display(), to.display());
+            }
+        }
+    }
+    let mut summary = format!(
+        "{} files would be deleted and {} replaced by links, reclaiming {}",
+        removed,
+        linked,
+        format_size(plan.reclaimed_bytes())
+    );
+    // (whether extents can be shared only shows when trying)
+    if plan.shareable_bytes() > 0 {
+        summary.push_str(&format!(" and up to {} by sharing extents, if the filesystem supports it", format_size(plan.shareable_bytes())));
+    }
+    println!("{}", summary);
+}
 
-            pair.push(file_data);
+// print what came up while planning or applying the changes (why files are kept only when verbose)
+fn print_notes(notes: &[Note], verbose: bool) {
+    for note in notes {
+        match note {
+            Note::Keeping { .. } if !verbose => {}
+            note if note.is_warning() => eprintln!("{}", note),
+            note => println!("{}", note),
         }
-        else {
-            // else create a new entry
-            let file_data = FileData {
-                filepath: e.path().to_path_buf(),
-                metadata: e.path().metadata().unwrap(),
-            };
-
-            let paths_buff: Vec<FileData> = vec![file_data];
-            duplicate_map.insert(basename, paths_buff);
+    }
+}
+
+// print the per-file errors of the run
+fn print_errors(errors: &[Error]) {
+    if errors.is_empty() {
+        return;
+    }
+    eprintln!("{} {}:", errors.len(), if errors.len() == 1 { "error" } else { "errors" });
+    for e in errors {
+        eprintln!("    {}", e);
+    }
+}
+
+// exit with a fatal error
+fn fatal(message: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(EXIT_FATAL);
+}
+
+// the scanner configured by the command line (and the configuration file)
+fn scanner(args: ScanArgs) -> Scanner {
+    let config = config::load(args.config.as_deref()).unwrap_or_else(|e| fatal(e));
+    let compound_extensions = normalize::compound_extensions(config.compound_extensions, args.compound_extensions);
+    let normalizer = Normalizer::new(&args.pattern, config.patterns, &compound_extensions)
+        .with_folding(args.ignore_case, args.unicode_normalize);
+    let includes = args.include.into_iter().chain(args.include_regex).collect();
+    let excludes = args.exclude.into_iter().chain(args.exclude_dir).chain(args.exclude_regex).collect();
+    Scanner::new(normalizer)
+        .filter(Filter::new(includes, excludes))
+        .references(args.references)
+        .cross_directory(args.cross_directory)
+        .include_symlinks(args.include_symlinks)
+        .byte_compare(args.byte_compare)
+}
+
+// the roots to scan, leaving out the ones inside another root
+fn distinct_roots(directories: &[PathBuf]) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    for (root, outer) in scan::nested_roots(directories).unwrap_or_else(|e| fatal(e)) {
+        match outer {
+            Some(outer) => eprintln!("{} is already scanned as part of {}, skipping it", root.display(), outer.display()),
+            None => roots.push(root),
         }
     }
+    roots
+}
 
-    // only keep the entries with more than one copies
-    duplicate_map.retain(|_, v| v.len() > 1);
+// the location of the saved scan, given or default
+fn results_path(path: Option<PathBuf>) -> PathBuf {
+    match path.map_or_else(results::default_path, Ok) {
+        Ok(path) => path,
+        Err(e) => fatal(format!("could not determine the location of the saved scan: {}", e)),
+    }
+}
 
-    
-    for values in duplicate_map.values_mut() {
-        // sort the vector based on their metadata (creation and modified time, oldest file first)
-        values.sort_by(compare_file_data);
+// the exit code for the errors of a run
+fn exit_code(errors: &[Error], no_duplicates: bool) -> i32 {
+    if !errors.is_empty() {
+        EXIT_PARTIAL_FAILURE
+    } else if no_duplicates {
+        EXIT_NO_DUPLICATES
+    } else {
+        EXIT_HANDLED
     }
-    duplicate_map
 }
 
-fn delete_duplicates(hashmap: HashMap<String, Vec<FileData>>) {
-    for (_k, v) in hashmap {
-        // delete all the duplicate files
-        for f in v.iter().take(v.len()-1) {
-            std::fs::remove_file(&f.filepath).unwrap();
-            //println!("{:#?}", f.filepath);
+// find the duplicates in a directory tree and save them
+fn scan(directories: Vec<PathBuf>, output: Option<PathBuf>, args: ScanArgs) -> i32 {
+    let output = results_path(output);
+    let roots = distinct_roots(&directories);
+    let references = args.references.clone();
+    let mut errors = Vec::new();
+    let scanner = scanner(args);
+    let verified = scanner.scan(&roots, &mut errors).unwrap_or_else(|e| fatal(e));
+    print_collisions(&verified.collisions, roots.len() > 1);
+    results::save(&output, &roots, &references, scanner.normalizer(), &verified).unwrap_or_else(|e| fatal(e));
+
+    let files: usize = verified.duplicates.iter().map(|group| group.files.len()).sum();
+    println!("{} groups of duplicates with {} files saved to {}", verified.duplicates.len(), files, output.display());
+    print_errors(&errors);
+    exit_code(&errors, verified.duplicates.is_empty())
+}
+
+// show the duplicates of a saved scan
+fn report(file: Option<PathBuf>) -> i32 {
+    let saved = results::load(&results_path(file)).unwrap_or_else(|e| fatal(e));
+    let roots: Vec<String> = saved.roots.iter().map(|root| root.display().to_string()).collect();
+    println!("scan of {} from {}", roots.join(", "), saved.timestamp);
+    for (title, groups) in [("duplicates:", &saved.duplicates), ("name collisions, different content:", &saved.collisions)] {
+        if groups.is_empty() {
+            continue;
         }
-        // rename the most updated file
-        let most_updated_file = v.last().unwrap();
-        let renamed_file = normalize_file(&v.last().unwrap().filepath.to_str().unwrap());
-        std::fs::rename(&most_updated_file.filepath, &renamed_file).unwrap();
+        println!("{}", title);
+        for group in groups {
+            println!("    {}", group.key.display());
+            for f in &group.files {
+                print_file(&f.path, results::root_of(&f.path, &saved.roots), Some(f.size), saved.roots.len() > 1, f.reference);
+            }
+        }
+    }
+    println!("{} groups of duplicates, reclaiming up to {}", saved.duplicates.len(), format_size(saved.reclaimable_bytes()));
+    exit_code(&[], saved.duplicates.is_empty())
+}
+
+// delete, trash or link the duplicates of a saved scan or a fresh scan of a directory
+fn clean(directories: Vec<PathBuf>, from: Option<Option<PathBuf>>, scan_args: ScanArgs, args: CleanArgs, journal: &mut Journal) -> i32 {
+    if args.keep.contains(&KeepPolicy::PathPriority) && args.prefer.is_empty() {
+        fatal("the path-priority keep policy needs at least one --prefer directory");
+    }
 
+    let mut errors = Vec::new();
+    let mut rename_only = None;
+    let (normalizer, duplicates) = match from {
+        // (names are normalized the way the scan did, whatever the configuration says by now)
+        Some(file) => {
+            let saved = results::load(&results_path(file)).unwrap_or_else(|e| fatal(e));
+            (saved.normalizer(), saved.verify(args.fail_fast, &mut errors))
+        }
+        None => {
+            let scanner = scanner(scan_args).fail_fast(args.fail_fast);
+            let roots = distinct_roots(&directories);
+            let mut name_groups = scanner.group(&roots, &mut errors).unwrap_or_else(|e| fatal(e));
+            // renaming numbered files doesn't need to look for duplicates at all
+            rename_only = args.rename_only.then(|| std::mem::take(&mut name_groups));
+            // only names shared by several files can be duplicates
+            name_groups.retain(|_, v| v.len() > 1);
+            // only files with identical contents are duplicates
+            let verified = scanner.verify(name_groups, &mut errors);
+            print_collisions(&verified.collisions, roots.len() > 1);
+            (scanner.normalizer().clone(), verified.duplicates)
+        }
+    };
+
+    // don't change anything if reading the tree already failed
+    if args.fail_fast && !errors.is_empty() {
+        print_errors(&errors);
+        return EXIT_PARTIAL_FAILURE;
     }
+
+    let action: Box<dyn Action> = match args.link {
+        Some(kind) => Box::new(Link(kind)),
+        None if args.trash => Box::new(Trash),
+        None => Box::new(Remove),
+    };
+    let keep_order = KeepOrder::new(args.keep, args.prefer, normalizer.clone());
+    let plan = match rename_only {
+        Some(name_groups) => plan_renames(name_groups, &normalizer, &keep_order),
+        None => plan_deletions(duplicates, action.as_ref(), &normalizer, &keep_order, !args.no_rename, args.on_conflict),
+    };
+    print_notes(&plan.notes, args.verbose);
+    let mut changed = !plan.is_empty();
+    if args.dry_run {
+        print_plan(&plan);
+    } else {
+        match plan.apply(journal, args.fail_fast) {
+            Ok(applied) => {
+                print_notes(&applied.notes, args.verbose);
+                // (e.g. none of the reflinks shared anything)
+                changed = applied.changed > 0;
+                errors.extend(applied.errors);
+            }
+            Err(e) => {
+                print_errors(&errors);
+                fatal(e);
+            }
+        }
+    }
+
+    print_errors(&errors);
+    exit_code(&errors, !changed)
+}
+
+// restore the files changed by a previous run
+fn undo(journal: &mut Journal, run: Option<&str>) -> i32 {
+    let undone = journal::undo(journal, run).unwrap_or_else(|e| fatal(e));
+    println!("undoing run {} from {}", undone.run, undone.timestamp);
+    for entry in &undone.restored {
+        let (path, target) = (entry.path.display(), entry.target.display());
+        match entry.kind {
+            EntryKind::Rename => println!("renamed {} -> {}", target, path),
+            EntryKind::Remove => println!("restored {} from {}", path, target),
+            EntryKind::Trash => println!("restored {} from the trash", path),
+            EntryKind::HardLink | EntryKind::Symlink => println!("unlinked {} from {}", path, target),
+            EntryKind::Restored | EntryKind::Undone => {}
+        }
+    }
+    if let Some(e) = undone.error {
+        fatal(e);
+    }
+    EXIT_HANDLED
 }
 
 fn main() {
     // get all the command line arguments
-    let args = CLI::parse();
+    let args = Cli::parse();
+
+    let journal_path = match args.journal.map_or_else(journal::default_path, Ok) {
+        Ok(path) => path,
+        Err(e) => fatal(format!("could not determine the journal location: {}", e)),
+    };
+    let mut journal = Journal::new(journal_path);
 
-    // read all the files and folders in the directory 
-    let path_iter = group_duplicates(&args.directory_path);
-    delete_duplicates(path_iter);
+    let code = match args.command {
+        Command::Scan { directories, output, scan: scan_args } => scan(directories, output, scan_args),
+        Command::Report { file } => report(file),
+        Command::Clean { directories, from, scan: scan_args, clean: clean_args } => clean(directories, from, scan_args, clean_args, &mut journal),
+        Command::Undo { run } => undo(&mut journal, run.as_deref()),
+    };
+    std::process::exit(code);
 }
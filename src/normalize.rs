@@ -0,0 +1,360 @@
+// recognising copies of a file by their name
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::str::FromStr;
+use regex::bytes::Regex;
+use crate::unicode;
+
+// any byte, so names that aren't valid utf-8 are normalized as well
+const ANY: &str = r"(?s-u:.)";
+// a single extension (bytes that aren't part of valid utf-8 count as word characters)
+const SINGLE_EXT: &str = r"\.(?:\w|(?-u:[\x80-\xff]))+";
+
+// extensions made of several parts, the copy marker comes before all of them
+pub const COMPOUND_EXTENSIONS: &[&str] = &[
+    ".tar.gz", ".tar.bz2", ".tar.xz", ".tar.zst", ".tar.lz", ".tar.lz4", ".tar.lzma", ".tar.br",
+    ".d.ts", ".d.mts", ".d.cts", ".min.js", ".min.css", ".js.map", ".css.map",
+];
+
+// upper bound for removing stacked suffixes like "name (1) (2).ext"
+const MAX_PASSES: usize = 16;
+
+// the built-in rule sets for the names different tools give to copies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    // "name (1).ext"
+    Numbered,
+    // "name(1).ext"
+    NumberedNoSpace,
+    // "name - Copy.ext", "name - Copy (2).ext"
+    WindowsCopy,
+    // "name copy.ext", "name copy 2.ext"
+    MacosCopy,
+    // "name_1.ext"
+    Underscore,
+    // "name-1.ext"
+    Dash,
+    // "Copy of name.ext", "Copy (2) of name.ext"
+    CopyOf,
+}
+
+impl Pattern {
+    // the regular expression of the rule, with a "base" and an optional "ext" capture
+    // (ext is the pattern of that capture)
+    fn regex(self, ext: &str) -> String {
+        match self {
+            Pattern::Numbered => format!(r"^(?P<base>{ANY}+?)\s+\(\d+\){ext}$"),
+            Pattern::NumberedNoSpace => format!(r"^(?P<base>{ANY}*?(?-u:[^\s]))\(\d+\){ext}$"),
+            Pattern::WindowsCopy => format!(r"^(?P<base>{ANY}+?) - Copy(?: \(\d+\))?{ext}$"),
+            Pattern::MacosCopy => format!(r"^(?P<base>{ANY}+?) copy(?: \d+)?{ext}$"),
+            Pattern::Underscore => format!(r"^(?P<base>{ANY}+?)_\d+{ext}$"),
+            Pattern::Dash => format!(r"^(?P<base>{ANY}+?)-\d+{ext}$"),
+            Pattern::CopyOf => format!(r"^Copy (?:\(\d+\) )?of (?P<base>{ANY}+)$"),
+        }
+    }
+
+    // the name the pattern is given by on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Pattern::Numbered => "numbered",
+            Pattern::NumberedNoSpace => "numbered-nospace",
+            Pattern::WindowsCopy => "windows-copy",
+            Pattern::MacosCopy => "macos-copy",
+            Pattern::Underscore => "underscore",
+            Pattern::Dash => "dash",
+            Pattern::CopyOf => "copy-of",
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "numbered" => Ok(Pattern::Numbered),
+            "numbered-nospace" => Ok(Pattern::NumberedNoSpace),
+            "windows-copy" => Ok(Pattern::WindowsCopy),
+            "macos-copy" => Ok(Pattern::MacosCopy),
+            "underscore" => Ok(Pattern::Underscore),
+            "dash" => Ok(Pattern::Dash),
+            "copy-of" => Ok(Pattern::CopyOf),
+            _ => Err(format!(
+                "unknown pattern '{}' (expected numbered, numbered-nospace, windows-copy, macos-copy, underscore, dash or copy-of)",
+                s
+            )),
+        }
+    }
+}
+
+// normalizes file names to the name of the original file, using a set of rules
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    rules: Vec<Regex>,
+    // splits a name into its base and extension
+    splitter: Regex,
+    // how the grouping key is folded (the names themselves keep their spelling)
+    ignore_case: bool,
+    unicode_normalize: bool,
+    // what the rules were built from, so a saved scan can build the same normalizer again
+    patterns: Vec<Pattern>,
+    user_rules: Vec<Regex>,
+    compound_extensions: Vec<String>,
+}
+
+impl Normalizer {
+    // the user defined rules are tried before the built-in patterns, which recognise the
+    // given compound extensions (e.g. ".tar.gz") besides single ones
+    pub fn new(patterns: &[Pattern], user_rules: Vec<Regex>, compound_extensions: &[String]) -> Self {
+        let ext = extension_regex(compound_extensions);
+        let mut rules = user_rules.clone();
+        rules.extend(patterns.iter().map(|pattern| Regex::new(&pattern.regex(&ext)).unwrap()));
+        let splitter = Regex::new(&format!(r"^(?P<base>{ANY}+?){ext}$")).unwrap();
+        Normalizer {
+            rules,
+            splitter,
+            ignore_case: false,
+            unicode_normalize: false,
+            patterns: patterns.to_vec(),
+            user_rules,
+            compound_extensions: compound_extensions.to_vec(),
+        }
+    }
+
+    // compare names regardless of case and/or unicode normalization (nfc vs nfd) when grouping
+    pub fn with_folding(mut self, ignore_case: bool, unicode_normalize: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self.unicode_normalize = unicode_normalize;
+        self
+    }
+
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    pub fn user_rules(&self) -> &[Regex] {
+        &self.user_rules
+    }
+
+    pub fn compound_extensions(&self) -> &[String] {
+        &self.compound_extensions
+    }
+
+    // whether names are compared regardless of case and of unicode normalization
+    pub fn folding(&self) -> (bool, bool) {
+        (self.ignore_case, self.unicode_normalize)
+    }
+
+    // the name files are grouped by: the normalized name, folded as configured
+    pub fn key(&self, filename: &OsStr) -> OsString {
+        let normalized = self.normalize(filename);
+        if !self.ignore_case && !self.unicode_normalize {
+            return normalized;
+        }
+        match normalized.to_str() {
+            Some(name) => {
+                let mut key = if self.unicode_normalize { unicode::nfd(name) } else { name.to_string() };
+                if self.ignore_case {
+                    // lowercasing can produce precomposed characters again
+                    key = key.to_lowercase();
+                    if self.unicode_normalize {
+                        key = unicode::nfd(&key);
+                    }
+                }
+                OsString::from(key)
+            }
+            // names that aren't valid utf-8 can only be folded as ascii
+            None if self.ignore_case => OsString::from_vec(normalized.as_bytes().to_ascii_lowercase()),
+            None => normalized,
+        }
+    }
+
+    // strips all copy markers from a file name (stacked ones like "name (1) (2).ext" included)
+    pub fn normalize(&self, filename: &OsStr) -> OsString {
+        let mut normalized = filename.as_bytes().to_vec();
+        for _ in 0..MAX_PASSES {
+            match self.strip_once(&normalized) {
+                Some(stripped) => normalized = stripped,
+                None => break,
+            }
+        }
+        OsString::from_vec(normalized)
+    }
+
+    // the name with a counter inserted before its extension ("name (n).ext")
+    pub fn numbered(&self, filename: &OsStr, n: usize) -> OsString {
+        let bytes = filename.as_bytes();
+        let (base, ext) = match self.splitter.captures(bytes) {
+            Some(capture) => (capture["base"].to_vec(), capture.name("ext").map_or(&b""[..], |ext| ext.as_bytes())),
+            None => (bytes.to_vec(), &b""[..]),
+        };
+        let mut numbered = base;
+        numbered.extend_from_slice(format!(" ({})", n).as_bytes());
+        numbered.extend_from_slice(ext);
+        OsString::from_vec(numbered)
+    }
+
+    // whether the file name carries a copy marker
+    pub fn is_numbered(&self, filename: &OsStr) -> bool {
+        self.strip_once(filename.as_bytes()).is_some()
+    }
+
+    // applies the first matching rule
+    fn strip_once(&self, filename: &[u8]) -> Option<Vec<u8>> {
+        self.rules.iter().find_map(|rule| {
+            let capture = rule.captures(filename)?;
+            // the basename capture
+            let mut stripped = capture.name("base")?.as_bytes().to_vec();
+            // handle extension cases as they are optional
+            stripped.extend_from_slice(capture.name("ext").map_or(b"", |ex| ex.as_bytes()));
+            Some(stripped)
+        })
+    }
+}
+
+// the compound extensions to recognise: the configured list replaces the built-in one, the
+// added ones (from the command line) extend either
+pub fn compound_extensions(configured: Option<Vec<String>>, added: Vec<String>) -> Vec<String> {
+    let mut extensions = configured.unwrap_or_else(|| COMPOUND_EXTENSIONS.iter().map(|ext| ext.to_string()).collect());
+    extensions.extend(added);
+    extensions
+}
+
+// validates an extension given by the user (e.g. ".tar.gz")
+pub fn check_extension(ext: &str) -> Result<String, String> {
+    if ext.len() < 2 || !ext.starts_with('.') || ext.contains('/') {
+        return Err(format!("invalid extension '{}' (expected something like '.tar.gz')", ext));
+    }
+    Ok(ext.to_string())
+}
+
+// the optional "ext" capture, trying the compound extensions (longest first) before a single one
+fn extension_regex(compound_extensions: &[String]) -> String {
+    let mut compound: Vec<&String> = compound_extensions.iter().collect();
+    compound.sort_by_key(|ext| std::cmp::Reverse(ext.len()));
+    let alternatives: String = compound.iter()
+        .map(|ext| format!("(?i:{})|", regex::escape(ext)))
+        .collect();
+    format!("(?P<ext>{}{})?", alternatives, SINGLE_EXT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: &[Pattern] = &[
+        Pattern::Numbered, Pattern::NumberedNoSpace, Pattern::WindowsCopy, Pattern::MacosCopy,
+        Pattern::Underscore, Pattern::Dash, Pattern::CopyOf,
+    ];
+
+    fn normalizer(patterns: &[Pattern]) -> Normalizer {
+        Normalizer::new(patterns, Vec::new(), &compound_extensions(None, Vec::new()))
+    }
+
+    #[test]
+    fn patterns() {
+        let cases: &[(Pattern, &str, &str)] = &[
+            (Pattern::Numbered, "name (1).txt", "name.txt"),
+            (Pattern::Numbered, "name (12)", "name"),
+            (Pattern::NumberedNoSpace, "name(1).txt", "name.txt"),
+            (Pattern::WindowsCopy, "name - Copy.txt", "name.txt"),
+            (Pattern::WindowsCopy, "name - Copy (2).txt", "name.txt"),
+            (Pattern::MacosCopy, "name copy.txt", "name.txt"),
+            (Pattern::MacosCopy, "name copy 2.txt", "name.txt"),
+            (Pattern::Underscore, "name_1.txt", "name.txt"),
+            (Pattern::Dash, "name-1.txt", "name.txt"),
+            (Pattern::CopyOf, "Copy of name.txt", "name.txt"),
+            (Pattern::CopyOf, "Copy (2) of name.txt", "name.txt"),
+        ];
+        for &(pattern, name, expected) in cases {
+            let only = normalizer(&[pattern]);
+            assert_eq!(only.normalize(OsStr::new(name)), OsStr::new(expected), "{} with {}", name, pattern.name());
+            assert!(only.is_numbered(OsStr::new(name)), "{} with {}", name, pattern.name());
+            assert!(!only.is_numbered(OsStr::new(expected)), "{} with {}", expected, pattern.name());
+        }
+        for pattern in ALL {
+            assert_eq!(pattern.name().parse::<Pattern>(), Ok(*pattern));
+        }
+    }
+
+    #[test]
+    fn stacked_and_unnumbered_names() {
+        let all = normalizer(ALL);
+        let cases: &[(&str, &str, bool)] = &[
+            ("name (1) (2).ext", "name.ext", true),
+            ("Copy of name - Copy (3).ext", "name.ext", true),
+            ("name.ext", "name.ext", false),
+            // a counter alone is the whole name, not a copy marker
+            ("(1).txt", "(1).txt", false),
+            (" (1).txt", " (1).txt", false),
+        ];
+        for &(name, expected, numbered) in cases {
+            assert_eq!(all.normalize(OsStr::new(name)), OsStr::new(expected), "{:?}", name);
+            assert_eq!(all.is_numbered(OsStr::new(name)), numbered, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn compound_extension_names() {
+        let all = normalizer(ALL);
+        let cases: &[(&str, &str)] = &[
+            ("backup (1).tar.gz", "backup.tar.gz"),
+            ("BACKUP (1).TAR.GZ", "BACKUP.TAR.GZ"),
+            ("types (2).d.ts", "types.d.ts"),
+            ("app copy.min.js", "app.min.js"),
+            // only the last part of an unknown compound extension is the extension
+            ("notes (1).v2.txt", "notes (1).v2.txt"),
+        ];
+        for &(name, expected) in cases {
+            assert_eq!(all.normalize(OsStr::new(name)), OsStr::new(expected), "{:?}", name);
+        }
+        assert_eq!(all.numbered(OsStr::new("a.tar.gz"), 3), OsStr::new("a (3).tar.gz"));
+        assert_eq!(all.numbered(OsStr::new("a.min.js"), 1), OsStr::new("a (1).min.js"));
+        assert_eq!(all.numbered(OsStr::new("a.txt"), 2), OsStr::new("a (2).txt"));
+        assert_eq!(all.numbered(OsStr::new("a"), 2), OsStr::new("a (2)"));
+    }
+
+    #[test]
+    fn configured_compound_extensions() {
+        let ext = |list: &[&str]| list.iter().map(|ext| ext.to_string()).collect::<Vec<String>>();
+        // added extensions extend the built-in list
+        let added = compound_extensions(None, ext(&[".tar.foo"]));
+        assert_eq!(added.len(), COMPOUND_EXTENSIONS.len() + 1);
+        assert!(added.iter().any(|e| e == ".tar.gz") && added.iter().any(|e| e == ".tar.foo"));
+        // a configured list replaces it
+        let configured = compound_extensions(Some(ext(&[".d.ts"])), ext(&[".tar.foo"]));
+        assert_eq!(configured, ext(&[".d.ts", ".tar.foo"]));
+        let normalizer = Normalizer::new(&[Pattern::Numbered], Vec::new(), &configured);
+        assert_eq!(normalizer.normalize(OsStr::new("a (1).tar.foo")), OsStr::new("a.tar.foo"));
+        assert_eq!(normalizer.normalize(OsStr::new("a (1).tar.gz")), OsStr::new("a (1).tar.gz"));
+    }
+
+    #[test]
+    fn folded_keys() {
+        let key = |normalizer: &Normalizer, name: &str| normalizer.key(OsStr::new(name));
+        let plain = normalizer(&[Pattern::Numbered]);
+        assert_eq!(key(&plain, "Caf\u{e9} (1).txt"), OsStr::new("Caf\u{e9}.txt"));
+        assert_ne!(key(&plain, "caf\u{e9}.txt"), key(&plain, "cafe\u{301}.txt"));
+
+        // nfc and nfd spellings get the same (decomposed) key
+        let unicode = normalizer(&[Pattern::Numbered]).with_folding(false, true);
+        assert_eq!(key(&unicode, "caf\u{e9} (1).txt"), OsStr::new("cafe\u{301}.txt"));
+        assert_eq!(key(&unicode, "cafe\u{301}.txt"), OsStr::new("cafe\u{301}.txt"));
+        assert_ne!(key(&unicode, "Cafe\u{301}.txt"), key(&unicode, "cafe\u{301}.txt"));
+
+        let case = normalizer(&[Pattern::Numbered]).with_folding(true, false);
+        assert_eq!(key(&case, "CAF\u{c9} (1).TXT"), OsStr::new("caf\u{e9}.txt"));
+        assert_ne!(key(&case, "caf\u{e9}.txt"), key(&case, "cafe\u{301}.txt"));
+
+        // the angstrom sign, "Å" and "å" in either normalization all get the same key
+        let both = normalizer(&[Pattern::Numbered]).with_folding(true, true);
+        for name in ["\u{212b}.txt", "\u{c5}.TXT", "\u{e5}.txt", "a\u{30a}.txt", "A\u{30a} (1).txt"] {
+            assert_eq!(key(&both, name), OsStr::new("a\u{30a}.txt"), "{:?}", name);
+        }
+
+        // names that aren't valid utf-8 are only folded as ascii
+        let latin1 = OsStr::from_bytes(b"CAF\xc9 (1).TXT");
+        assert_eq!(both.key(latin1), OsStr::from_bytes(b"caf\xc9.txt"));
+        assert_eq!(unicode.key(latin1), OsStr::from_bytes(b"CAF\xc9.TXT"));
+    }
+}
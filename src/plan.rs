@@ -0,0 +1,605 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::error::{Error, Result};
+use crate::hash::Digest;
+use crate::journal::{EntryKind, Journal};
+use crate::keep::KeepOrder;
+use crate::link::LinkKind;
+use crate::normalize::Normalizer;
+use crate::scan::{DuplicateGroup, FileData};
+use crate::{link, sys, trash};
+
+// what happens when the normalized name of the kept file is already taken by another file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    // leave the whole group alone
+    Skip,
+    // delete the duplicates but don't rename the kept file
+    KeepNumbered,
+    // rename the kept file to the first free "name (n).ext" instead
+    NextFree,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnConflict::Skip),
+            "keep-numbered" => Ok(OnConflict::KeepNumbered),
+            "next-free" => Ok(OnConflict::NextFree),
+            _ => Err(format!("unknown conflict strategy '{}' (expected skip, keep-numbered or next-free)", s)),
+        }
+    }
+}
+
+// a single change to the filesystem
+#[derive(Debug)]
+pub enum Operation {
+    // delete a duplicate file (kept is the file with the same contents that stays)
+    Remove { path: PathBuf, size: u64, hash: Option<Digest>, kept: PathBuf },
+    // move a duplicate file to the trash
+    Trash { path: PathBuf, size: u64, hash: Option<Digest> },
+    // replace a duplicate file with a hard link to the kept file
+    HardLink { path: PathBuf, size: u64, hash: Option<Digest>, kept: PathBuf },
+    // share the extents of a duplicate file with the kept file (both paths and their metadata stay)
+    Reflink { path: PathBuf, size: u64, kept: PathBuf },
+    // replace a duplicate file with a symbolic link to the kept file
+    Symlink { path: PathBuf, size: u64, hash: Option<Digest>, kept: PathBuf, relative: bool },
+    // rename the kept file to its normalized name
+    Rename { from: PathBuf, to: PathBuf, size: u64, hash: Option<Digest> },
+}
+
+// something worth reporting about planning or applying the changes, besides the changes themselves
+#[derive(Debug)]
+pub enum Note {
+    // the file kept of a group and why it was chosen over (the first of) its duplicates
+    Keeping { kept: PathBuf, other: PathBuf, reason: String },
+    // a duplicate the action leaves alone
+    Skipped { path: PathBuf, reason: String },
+    // the normalized name of the kept file is taken by another file
+    Conflict { target: PathBuf, kept: PathBuf, on_conflict: OnConflict },
+    // the bytes of the duplicates of a group the filesystem now shares with the kept file
+    Deduplicated { kept: PathBuf, deduped: u64, size: u64 },
+    // the filesystem of a duplicate can't share extents, no more reflinks are tried on it
+    ReflinkUnsupported { path: PathBuf },
+}
+
+impl Note {
+    // whether the note is about something left undone, rather than about progress
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Note::Skipped { .. } | Note::Conflict { .. } | Note::ReflinkUnsupported { .. })
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Note::Keeping { kept, other, reason } => write!(f, "keeping {} over {}: {}", kept.display(), other.display(), reason),
+            Note::Skipped { path, reason } => write!(f, "skipping {}: {}", path.display(), reason),
+            Note::Conflict { target, kept, on_conflict } => {
+                let resolution = match on_conflict {
+                    OnConflict::Skip => format!("leaving the duplicates of {} alone", kept.display()),
+                    OnConflict::KeepNumbered => format!("keeping {} under its name", kept.display()),
+                    OnConflict::NextFree => format!("renaming {} to the next free name", kept.display()),
+                };
+                write!(f, "{} already exists, {}", target.display(), resolution)
+            }
+            Note::Deduplicated { kept, deduped, size } => {
+                write!(f, "deduplicated {} of {} against {}", format_size(*deduped), format_size(*size), kept.display())
+            }
+            Note::ReflinkUnsupported { path } => write!(f, "{}: the filesystem doesn't support sharing extents, skipping it", path.display()),
+        }
+    }
+}
+
+// all the changes of a run, in the order they are applied (grouped by duplicate group)
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub groups: Vec<Vec<Operation>>,
+    // why files were kept or left alone while planning
+    pub notes: Vec<Note>,
+}
+
+// what applying a plan reported
+#[derive(Debug, Default)]
+pub struct Applied {
+    // the per-file errors (each one skipped the rest of its group)
+    pub errors: Vec<Error>,
+    pub notes: Vec<Note>,
+    // the number of operations that changed something (reflinks that shared no extents don't count)
+    pub changed: usize,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.groups.iter().all(Vec::is_empty)
+    }
+
+    pub fn operations(&self) -> impl Iterator<Item = &Operation> {
+        self.groups.iter().flatten()
+    }
+
+    // the number of bytes freed by applying the plan (not counting reflinks)
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.operations()
+            .map(|op| match op {
+                Operation::Remove { size, .. }
+                | Operation::Trash { size, .. }
+                | Operation::HardLink { size, .. }
+                | Operation::Symlink { size, .. } => *size,
+                Operation::Reflink { .. } | Operation::Rename { .. } => 0,
+            })
+            .sum()
+    }
+
+    // the number of bytes reflinks could share, if the filesystem supports it and the extents
+    // aren't shared already
+    pub fn shareable_bytes(&self) -> u64 {
+        self.operations()
+            .map(|op| match op {
+                Operation::Reflink { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    // apply the operations in order, recording them in the journal
+    //
+    // a failed operation skips the rest of its group (so the kept file isn't renamed over a
+    // duplicate that couldn't be deleted) and the run continues with the next group, unless
+    // fail_fast is set. the per-file errors are returned, failing to write the journal stops
+    // the run right away.
+    pub fn apply(&self, journal: &mut Journal, fail_fast: bool) -> Result<Applied> {
+        let mut applied = Applied::default();
+        // devices whose filesystem can't share extents
+        let mut unsupported: HashSet<u64> = HashSet::new();
+
+        for group in &self.groups {
+            // bytes actually deduplicated and bytes requested
+            let mut reflinked: Option<(&Path, u64, u64)> = None;
+
+            for op in group {
+                match apply_operation(op, journal, &mut unsupported, &mut applied.notes) {
+                    Ok(Some(deduped)) => {
+                        if let Operation::Reflink { size, kept, .. } = op {
+                            let (_, total_deduped, total_size) = reflinked.get_or_insert((kept, 0, 0));
+                            *total_deduped += deduped;
+                            *total_size += size;
+                        }
+                        if deduped > 0 || !matches!(op, Operation::Reflink { .. }) {
+                            applied.changed += 1;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e @ Error::Journal(_)) => return Err(e),
+                    Err(e) => {
+                        applied.errors.push(e);
+                        if fail_fast {
+                            return Ok(applied);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            if let Some((kept, deduped, size)) = reflinked {
+                applied.notes.push(Note::Deduplicated { kept: kept.to_path_buf(), deduped, size });
+            }
+        }
+        Ok(applied)
+    }
+}
+
+// what happens to the duplicates of a group, once the file to keep is chosen
+//
+// actions choose among the built-in operations (which the journal knows how to undo), they
+// can't add operations of their own
+pub trait Action {
+    // the operation applied to a duplicate of the kept file: None leaves the duplicate alone
+    // quietly (e.g. it is the kept file already), an error leaves it alone for the given reason
+    fn operation(&self, duplicate: &FileData, kept: &FileData) -> std::result::Result<Option<Operation>, String>;
+
+    // whether the kept file is renamed to its normalized name afterwards
+    fn renames_kept(&self) -> bool {
+        true
+    }
+}
+
+// delete the duplicates
+#[derive(Debug, Clone, Copy)]
+pub struct Remove;
+
+impl Action for Remove {
+    fn operation(&self, duplicate: &FileData, kept: &FileData) -> std::result::Result<Option<Operation>, String> {
+        Ok(Some(Operation::Remove {
+            path: duplicate.filepath.clone(),
+            size: duplicate.reclaimable_size(),
+            hash: duplicate.hash,
+            kept: kept.filepath.clone(),
+        }))
+    }
+}
+
+// move the duplicates to the trash
+#[derive(Debug, Clone, Copy)]
+pub struct Trash;
+
+impl Action for Trash {
+    fn operation(&self, duplicate: &FileData, _kept: &FileData) -> std::result::Result<Option<Operation>, String> {
+        Ok(Some(Operation::Trash {
+            path: duplicate.filepath.clone(),
+            size: duplicate.reclaimable_size(),
+            hash: duplicate.hash,
+        }))
+    }
+}
+
+// replace the duplicates with links to the kept file (which then keeps its path as well)
+#[derive(Debug, Clone, Copy)]
+pub struct Link(pub LinkKind);
+
+impl Action for Link {
+    fn operation(&self, duplicate: &FileData, kept_file: &FileData) -> std::result::Result<Option<Operation>, String> {
+        let path = duplicate.filepath.clone();
+        let size = duplicate.reclaimable_size();
+        let hash = duplicate.hash;
+        let kept = kept_file.filepath.clone();
+        if kept_file.metadata.is_symlink() {
+            return Err(format!("can't link it to {}, which is a symbolic link already", kept.display()));
+        }
+        match self.0 {
+            LinkKind::Hard => {
+                if duplicate.metadata.dev() != kept_file.metadata.dev() {
+                    return Err(format!("can't hard link it to {}, which is on a different filesystem", kept.display()));
+                }
+                if duplicate.metadata.ino() == kept_file.metadata.ino() {
+                    // already the same file
+                    return Ok(None);
+                }
+                Ok(Some(Operation::HardLink { path, size, hash, kept }))
+            }
+            LinkKind::Reflink => {
+                if duplicate.metadata.dev() != kept_file.metadata.dev() {
+                    return Err(format!("can't share its extents with {}, which is on a different filesystem", kept.display()));
+                }
+                if duplicate.metadata.ino() == kept_file.metadata.ino() {
+                    return Ok(None);
+                }
+                Ok(Some(Operation::Reflink { path, size, kept }))
+            }
+            LinkKind::Symlink { relative } => Ok(Some(Operation::Symlink { path, size, hash, kept, relative })),
+        }
+    }
+
+    fn renames_kept(&self) -> bool {
+        false
+    }
+}
+
+// plan the deletion of all duplicates, keeping the file chosen by the keep order under its normalized name
+// (unless the action keeps the paths of the duplicates, like links do, or rename is unset)
+//
+// files in reference directories are never changed, a group with one of them keeps it as it is
+// and deletes all the copies outside of the reference directories
+//
+// why each file was kept, duplicates the action leaves alone and rename conflicts end up in the notes
+pub fn plan_deletions(
+    groups: Vec<DuplicateGroup>,
+    action: &dyn Action,
+    normalizer: &Normalizer,
+    keep_order: &KeepOrder,
+    rename: bool,
+    on_conflict: OnConflict,
+) -> Plan {
+    let mut plan = Plan::default();
+    // the rename targets of the groups planned so far
+    let mut claimed: HashSet<PathBuf> = HashSet::new();
+    for group in groups {
+        let mut v = group.files;
+        // the file to keep comes first
+        v.sort_by(|fd1, fd2| keep_order.compare(fd1, fd2));
+        let kept_file = &v[0];
+        let kept = kept_file.filepath.clone();
+        // (the copies inside reference directories are never touched)
+        let Some(other) = v.iter().skip(1).find(|f| !f.reference) else {
+            continue;
+        };
+        plan.notes.push(Note::Keeping {
+            kept: kept.clone(),
+            other: other.filepath.clone(),
+            reason: keep_order.explain(kept_file, other),
+        });
+        // delete (or replace) all the duplicate files, except the ones in reference directories
+        let mut operations = Vec::new();
+        for f in v.iter().skip(1).filter(|f| !f.reference) {
+            match action.operation(f, kept_file) {
+                Ok(Some(operation)) => operations.push(operation),
+                Ok(None) => {}
+                Err(reason) => plan.notes.push(Note::Skipped { path: f.filepath.clone(), reason }),
+            }
+        }
+        if !rename || !action.renames_kept() || kept_file.reference {
+            plan.groups.push(operations);
+            continue;
+        }
+        // rename the kept file (staying in its own directory), unless that would replace another
+        // file: the target has to be free or one of the duplicates removed just before
+        let filename = kept_file.filepath.file_name().unwrap();
+        let is_free = |path: &Path| {
+            !claimed.contains(path)
+                && (path.symlink_metadata().is_err() || v.iter().skip(1).any(|f| f.filepath == path && !f.reference))
+        };
+        let mut renamed_file = kept_file.filepath.with_file_name(normalizer.normalize(filename));
+        if renamed_file != kept_file.filepath && !is_free(&renamed_file) {
+            match on_conflict {
+                OnConflict::Skip => {
+                    plan.notes.push(Note::Conflict { target: renamed_file, kept, on_conflict });
+                    continue;
+                }
+                OnConflict::KeepNumbered => {
+                    plan.notes.push(Note::Conflict { target: renamed_file, kept: kept.clone(), on_conflict });
+                    renamed_file = kept.clone();
+                }
+                OnConflict::NextFree => {
+                    let normalized = normalizer.normalize(filename);
+                    // (the kept file's own name counts as free, so it may just keep it)
+                    renamed_file = (1..)
+                        .map(|n| kept.with_file_name(normalizer.numbered(&normalized, n)))
+                        .find(|path| *path == kept || is_free(path))
+                        .unwrap();
+                }
+            }
+        }
+        if renamed_file != kept_file.filepath {
+            claimed.insert(renamed_file.clone());
+            operations.push(Operation::Rename {
+                from: kept_file.filepath.clone(),
+                to: renamed_file,
+                size: kept_file.metadata.len(),
+                hash: kept_file.hash,
+            });
+        }
+        plan.groups.push(operations);
+    }
+    plan
+}
+
+// plan renaming numbered files to their normalized name where no file has that name yet
+// (of several files with the same normalized name, the one chosen by the keep order gets it)
+//
+// a directory where a file of the group is unnumbered already is left alone, its name may only
+// differ in case or unicode normalization from the one the others would be renamed to
+pub fn plan_renames(name_groups: HashMap<PathBuf, Vec<FileData>>, normalizer: &Normalizer, keep_order: &KeepOrder) -> Plan {
+    let mut plan = Plan::default();
+    // the rename targets of the groups planned so far
+    let mut claimed: HashSet<PathBuf> = HashSet::new();
+    for mut files in name_groups.into_values() {
+        files.sort_by(|fd1, fd2| keep_order.compare(fd1, fd2));
+        let named: HashSet<PathBuf> = files.iter()
+            .filter(|f| !normalizer.is_numbered(f.filepath.file_name().unwrap()))
+            .filter_map(|f| f.filepath.parent().map(Path::to_path_buf))
+            .collect();
+        for f in files.into_iter().filter(|f| !f.reference) {
+            let renamed_file = f.filepath.with_file_name(normalizer.normalize(f.filepath.file_name().unwrap()));
+            if renamed_file == f.filepath || claimed.contains(&renamed_file) || renamed_file.symlink_metadata().is_ok() {
+                continue;
+            }
+            if f.filepath.parent().is_some_and(|parent| named.contains(parent)) {
+                continue;
+            }
+            claimed.insert(renamed_file.clone());
+            plan.groups.push(vec![Operation::Rename {
+                from: f.filepath,
+                to: renamed_file,
+                size: f.metadata.len(),
+                hash: None,
+            }]);
+        }
+    }
+    plan
+}
+
+// apply a single operation, returning None if it was skipped and otherwise the number of
+// deduplicated bytes for reflinks (0 for everything else)
+fn apply_operation(op: &Operation, journal: &mut Journal, unsupported: &mut HashSet<u64>, notes: &mut Vec<Note>) -> Result<Option<u64>> {
+    match op {
+        Operation::Remove { path, size, hash, kept } => {
+            std::fs::remove_file(path).map_err(|e| Error::io(path, e))?;
+            journal.record(EntryKind::Remove, path, kept, *size, hash.as_ref()).map_err(Error::Journal)?;
+        }
+        Operation::Trash { path, size, hash } => {
+            let location = trash::trash_file(path).map_err(|e| Error::io(path, e))?;
+            journal.record(EntryKind::Trash, path, &location, *size, hash.as_ref()).map_err(Error::Journal)?;
+        }
+        Operation::HardLink { path, size, hash, kept } => {
+            link::hard_link(kept, path).map_err(|e| Error::io(path, e))?;
+            journal.record(EntryKind::HardLink, path, kept, *size, hash.as_ref()).map_err(Error::Journal)?;
+        }
+        Operation::Symlink { path, size, hash, kept, relative } => {
+            link::symlink(kept, path, *relative).map_err(|e| Error::io(path, e))?;
+            journal.record(EntryKind::Symlink, path, kept, *size, hash.as_ref()).map_err(Error::Journal)?;
+        }
+        Operation::Reflink { path, kept, .. } => {
+            // reflinks don't change any path, so there is nothing to undo or journal
+            let device = path.metadata().map_err(|e| Error::io(path, e))?.dev();
+            if unsupported.contains(&device) {
+                return Ok(None);
+            }
+            return match link::reflink(kept, path) {
+                Ok(deduped) => Ok(Some(deduped)),
+                // (reported once, the other reflinks on the filesystem are skipped quietly)
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    unsupported.insert(device);
+                    notes.push(Note::ReflinkUnsupported { path: path.clone() });
+                    Ok(None)
+                }
+                Err(e) => Err(Error::io(path, e)),
+            };
+        }
+        Operation::Rename { from, to, size, hash } => {
+            // never replace a file that appeared at the target since planning
+            sys::rename_noreplace(from, to).map_err(|e| Error::io(from, e))?;
+            journal.record(EntryKind::Rename, from, to, *size, hash.as_ref()).map_err(Error::Journal)?;
+        }
+    }
+    Ok(Some(0))
+}
+
+// formats a byte count for humans (e.g. "1.5 MiB")
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keep::KeepPolicy;
+    use crate::normalize::Pattern;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // a group of files created with the given contents
+    fn group(dir: &Path, files: &[(&str, &str)]) -> DuplicateGroup {
+        let files = files.iter().map(|&(name, contents)| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            let metadata = path.symlink_metadata().unwrap();
+            FileData { filepath: path, root: dir.to_path_buf(), reference: false, metadata, hash: None }
+        }).collect();
+        DuplicateGroup { key: PathBuf::from("a.txt"), files }
+    }
+
+    fn plan(groups: Vec<DuplicateGroup>, on_conflict: OnConflict) -> Plan {
+        let normalizer = Normalizer::new(&[Pattern::Numbered], Vec::new(), &[]);
+        // the larger file is kept, the path decides between files of the same size
+        let keep_order = KeepOrder::new(vec![KeepPolicy::Largest], Vec::new(), normalizer.clone());
+        plan_deletions(groups, &Remove, &normalizer, &keep_order, true, on_conflict)
+    }
+
+    // the operations as (kind, path, target) relative to dir
+    fn operations(plan: &Plan, dir: &Path) -> Vec<(&'static str, PathBuf, PathBuf)> {
+        let relative = |path: &Path| path.strip_prefix(dir).unwrap().to_path_buf();
+        plan.operations().map(|op| match op {
+            Operation::Remove { path, kept, .. } => ("remove", relative(path), relative(kept)),
+            Operation::Rename { from, to, .. } => ("rename", relative(from), relative(to)),
+            op => panic!("unexpected {:?}", op),
+        }).collect()
+    }
+
+    fn op(kind: &'static str, path: &str, target: &str) -> (&'static str, PathBuf, PathBuf) {
+        (kind, PathBuf::from(path), PathBuf::from(target))
+    }
+
+    fn conflicts(plan: &Plan) -> Vec<(PathBuf, PathBuf)> {
+        plan.notes.iter().filter_map(|note| match note {
+            Note::Conflict { target, kept, .. } => Some((target.clone(), kept.clone())),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn conflict_strategies() {
+        let dir = temp_dir("conflicts");
+        // another file already has the normalized name (and the first counter)
+        std::fs::write(dir.join("a.txt"), "other").unwrap();
+        std::fs::write(dir.join("a (1).txt"), "other").unwrap();
+        let files = [("a (3).txt", "same"), ("a (5).txt", "same")];
+
+        let skipped = plan(vec![group(&dir, &files)], OnConflict::Skip);
+        assert!(skipped.is_empty());
+        assert_eq!(conflicts(&skipped), [(dir.join("a.txt"), dir.join("a (3).txt"))]);
+
+        let numbered = plan(vec![group(&dir, &files)], OnConflict::KeepNumbered);
+        assert_eq!(operations(&numbered, &dir), [op("remove", "a (5).txt", "a (3).txt")]);
+        assert_eq!(conflicts(&numbered).len(), 1);
+
+        let next_free = plan(vec![group(&dir, &files)], OnConflict::NextFree);
+        assert_eq!(operations(&next_free, &dir), [op("remove", "a (5).txt", "a (3).txt"), op("rename", "a (3).txt", "a (2).txt")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removed_duplicates_free_their_name() {
+        let dir = temp_dir("freed");
+        let plan = plan(vec![group(&dir, &[("a.txt", "x"), ("a (1).txt", "xx")])], OnConflict::Skip);
+        assert_eq!(operations(&plan, &dir), [op("remove", "a.txt", "a (1).txt"), op("rename", "a (1).txt", "a.txt")]);
+        assert!(conflicts(&plan).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn groups_claiming_the_same_name() {
+        let dir = temp_dir("claimed");
+        let groups = || vec![group(&dir, &[("a (1).txt", "1"), ("a (2).txt", "1")]), group(&dir, &[("a (3).txt", "3"), ("a (4).txt", "3")])];
+
+        let skipped = plan(groups(), OnConflict::Skip);
+        assert_eq!(operations(&skipped, &dir), [op("remove", "a (2).txt", "a (1).txt"), op("rename", "a (1).txt", "a.txt")]);
+        assert_eq!(conflicts(&skipped), [(dir.join("a.txt"), dir.join("a (3).txt"))]);
+
+        // (the counters up to its own are taken, so the second kept file keeps its name)
+        let next_free = plan(groups(), OnConflict::NextFree);
+        assert_eq!(operations(&next_free, &dir), [
+            op("remove", "a (2).txt", "a (1).txt"),
+            op("rename", "a (1).txt", "a.txt"),
+            op("remove", "a (4).txt", "a (3).txt"),
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renames_never_replace_files() {
+        let dir = temp_dir("noreplace");
+        let plan = plan(vec![group(&dir, &[("a (1).txt", "same"), ("a (2).txt", "same")])], OnConflict::Skip);
+        assert_eq!(operations(&plan, &dir), [op("remove", "a (2).txt", "a (1).txt"), op("rename", "a (1).txt", "a.txt")]);
+        // a file appearing at the target after planning stays
+        std::fs::write(dir.join("a.txt"), "new").unwrap();
+        let mut journal = Journal::new(dir.join("journal"));
+        let applied = plan.apply(&mut journal, false).unwrap();
+        assert_eq!(applied.changed, 1);
+        assert!(matches!(&applied.errors[..], [Error::Io { source, .. }] if source.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt")).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(dir.join("a (1).txt")).unwrap(), "same");
+
+        let error = sys::rename_noreplace(&dir.join("a (1).txt"), &dir.join("a.txt")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renaming_numbered_files() {
+        let dir = temp_dir("renames");
+        let normalizer = Normalizer::new(&[Pattern::Numbered], Vec::new(), &[]).with_folding(true, true);
+        let keep_order = KeepOrder::new(vec![KeepPolicy::ShortestPath], Vec::new(), normalizer.clone());
+        let renames = |files: &[(&str, &str)]| {
+            let name_groups = HashMap::from([(PathBuf::from("photo.jpg"), group(&dir, files).files)]);
+            plan_renames(name_groups, &normalizer, &keep_order)
+        };
+
+        let numbered = renames(&[("photo (1).jpg", "1"), ("photo (22).jpg", "2")]);
+        assert_eq!(operations(&numbered, &dir), [op("rename", "photo (1).jpg", "photo.jpg")]);
+        // the name is taken already, just spelled differently
+        let spelled = renames(&[("Photo.JPG", "1"), ("photo (1).jpg", "2")]);
+        assert!(spelled.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
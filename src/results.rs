@@ -0,0 +1,342 @@
+// saving the results of a scan, so they can be reviewed before cleaning up in a separate step
+//
+// every line has tab separated fields, starting with the kind of the line:
+//     scan, timestamp
+//     root or reference, path
+//     pattern, built-in pattern name / rule, user defined regex / compound-ext, extension
+//     ignore-case / unicode-normalize (without fields, when the names were folded)
+//     duplicate or collision, group number, key, path, size, content hash, "reference" or "-"
+// (symbolic links store the hash of their target instead of any contents, and a size of 0 as
+// removing them frees nothing)
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use regex::bytes::Regex;
+use crate::error::{Error, Result};
+use crate::hash::{self, Digest, Sha256};
+use crate::journal::{self, escape, unescape};
+use crate::normalize::{Normalizer, Pattern};
+use crate::scan::{self, compare_file_data, DuplicateGroup, FileData, VerifiedGroups};
+use crate::sys;
+
+// a file as it was when it was scanned
+#[derive(Debug)]
+pub struct SavedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: Option<Digest>,
+    // whether the file is inside one of the reference directories
+    pub reference: bool,
+}
+
+#[derive(Debug)]
+pub struct SavedGroup {
+    pub key: PathBuf,
+    pub files: Vec<SavedFile>,
+}
+
+#[derive(Debug, Default)]
+pub struct SavedScan {
+    pub timestamp: String,
+    pub roots: Vec<PathBuf>,
+    pub references: Vec<PathBuf>,
+    // how the scan recognised copies, so cleaning up renames the kept files like a fresh scan would
+    pub patterns: Vec<Pattern>,
+    pub rules: Vec<Regex>,
+    pub compound_extensions: Vec<String>,
+    pub ignore_case: bool,
+    pub unicode_normalize: bool,
+    pub duplicates: Vec<SavedGroup>,
+    pub collisions: Vec<SavedGroup>,
+}
+
+// $XDG_STATE_HOME/uniquer/scan, defaulting to ~/.local/state/uniquer/scan
+pub fn default_path() -> io::Result<PathBuf> {
+    Ok(journal::state_dir()?.join("scan"))
+}
+
+// write the groups found below the roots and reference directories to a file (replacing an earlier scan)
+pub fn save(path: &Path, roots: &[PathBuf], references: &[PathBuf], normalizer: &Normalizer, groups: &VerifiedGroups) -> Result<()> {
+    let mut contents = Vec::new();
+    let timestamp = sys::format_local_time(journal::unix_time()).map_err(|e| Error::io(path, e))?;
+    contents.extend_from_slice(format!("scan\t{}\n", timestamp).as_bytes());
+    for (kind, root) in roots.iter().map(|r| ("root", r)).chain(references.iter().map(|r| ("reference", r))) {
+        let root = std::path::absolute(root).map_err(|e| Error::io(root, e))?;
+        contents.extend_from_slice(format!("{}\t", kind).as_bytes());
+        contents.extend_from_slice(&escape(&root));
+        contents.push(b'\n');
+    }
+    for pattern in normalizer.patterns() {
+        contents.extend_from_slice(format!("pattern\t{}\n", pattern.name()).as_bytes());
+    }
+    let settings = normalizer.user_rules().iter().map(|rule| ("rule", rule.as_str()))
+        .chain(normalizer.compound_extensions().iter().map(|ext| ("compound-ext", ext.as_str())));
+    for (kind, setting) in settings {
+        contents.extend_from_slice(format!("{}\t", kind).as_bytes());
+        contents.extend_from_slice(&escape(Path::new(setting)));
+        contents.push(b'\n');
+    }
+    let (ignore_case, unicode_normalize) = normalizer.folding();
+    if ignore_case {
+        contents.extend_from_slice(b"ignore-case\n");
+    }
+    if unicode_normalize {
+        contents.extend_from_slice(b"unicode-normalize\n");
+    }
+    for (kind, groups) in [("duplicate", &groups.duplicates), ("collision", &groups.collisions)] {
+        for (number, group) in groups.iter().enumerate() {
+            for f in &group.files {
+                let hash = if f.metadata.is_symlink() {
+                    Some(target_hash(&f.filepath).map_err(|e| Error::io(&f.filepath, e))?)
+                } else {
+                    f.hash
+                };
+                let filepath = std::path::absolute(&f.filepath).map_err(|e| Error::io(&f.filepath, e))?;
+                contents.extend_from_slice(format!("{}\t{}\t", kind, number).as_bytes());
+                contents.extend_from_slice(&escape(&group.key));
+                contents.push(b'\t');
+                contents.extend_from_slice(&escape(&filepath));
+                contents.extend_from_slice(format!("\t{}\t", f.reclaimable_size()).as_bytes());
+                contents.extend_from_slice(hash.map_or("-".to_string(), |h| hash::to_hex(&h)).as_bytes());
+                // (the flag the scan decided on the resolved paths, which a prefix of the saved
+                // path can't tell when a reference was given through a symlink)
+                contents.extend_from_slice(if f.reference { b"\treference\n" } else { b"\t-\n" });
+            }
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+    }
+    std::fs::write(path, contents).map_err(|e| Error::io(path, e))
+}
+
+// read a scan saved by save
+pub fn load(path: &Path) -> Result<SavedScan> {
+    let reader = BufReader::new(File::open(path).map_err(|e| Error::io(path, e))?);
+    let mut saved = SavedScan::default();
+    // the group number of the last line, to know when a new group starts
+    let mut last_group: Option<(bool, usize)> = None;
+    for (number, line) in reader.split(b'\n').enumerate() {
+        let line = line.map_err(|e| Error::io(path, e))?;
+        if line.is_empty() {
+            continue;
+        }
+        let malformed = || Error::io(path, io::Error::new(io::ErrorKind::InvalidData, format!("line {}: malformed scan result", number + 1)));
+        let fields: Vec<&[u8]> = line.split(|&b| b == b'\t').collect();
+        let text = |field: &[u8]| std::str::from_utf8(field).ok().map(str::to_string);
+        let setting = |field: &[u8]| unescape(field).into_os_string().into_string().ok();
+        match (fields[0], fields.len()) {
+            (b"scan", 2) => saved.timestamp = text(fields[1]).ok_or_else(malformed)?,
+            (b"root", 2) => saved.roots.push(unescape(fields[1])),
+            (b"reference", 2) => saved.references.push(unescape(fields[1])),
+            (b"pattern", 2) => saved.patterns.push(text(fields[1]).and_then(|p| p.parse().ok()).ok_or_else(malformed)?),
+            (b"rule", 2) => saved.rules.push(setting(fields[1]).and_then(|r| Regex::new(&r).ok()).ok_or_else(malformed)?),
+            (b"compound-ext", 2) => saved.compound_extensions.push(setting(fields[1]).ok_or_else(malformed)?),
+            (b"ignore-case", 1) => saved.ignore_case = true,
+            (b"unicode-normalize", 1) => saved.unicode_normalize = true,
+            (kind @ (b"duplicate" | b"collision"), 7) => {
+                let duplicate = kind == b"duplicate";
+                let group: usize = text(fields[1]).and_then(|g| g.parse().ok()).ok_or_else(malformed)?;
+                let file = SavedFile {
+                    path: unescape(fields[3]),
+                    size: text(fields[4]).and_then(|s| s.parse().ok()).ok_or_else(malformed)?,
+                    hash: match fields[5] {
+                        b"-" => None,
+                        hex => Some(text(hex).and_then(|h| hash::from_hex(&h)).ok_or_else(malformed)?),
+                    },
+                    reference: match fields[6] {
+                        b"reference" => true,
+                        b"-" => false,
+                        _ => return Err(malformed()),
+                    },
+                };
+                let groups = if duplicate { &mut saved.duplicates } else { &mut saved.collisions };
+                if last_group != Some((duplicate, group)) {
+                    groups.push(SavedGroup { key: unescape(fields[2]), files: Vec::new() });
+                    last_group = Some((duplicate, group));
+                }
+                groups.last_mut().unwrap().files.push(file);
+            }
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(saved)
+}
+
+impl SavedScan {
+    // the normalizer the scan grouped the names with
+    pub fn normalizer(&self) -> Normalizer {
+        Normalizer::new(&self.patterns, self.rules.clone(), &self.compound_extensions)
+            .with_folding(self.ignore_case, self.unicode_normalize)
+    }
+
+    // the bytes freed by removing all but one file of every group of duplicates
+    // (or all files outside the reference directories, if the group has any inside)
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.duplicates.iter()
+            .flat_map(|group| {
+                let kept = if group.files.iter().any(|f| f.reference) { 0 } else { 1 };
+                group.files.iter().filter(|f| !f.reference).skip(kept)
+            })
+            .map(|f| f.size)
+            .sum()
+    }
+
+    // the groups of duplicates, with every file checked to be unchanged since the scan
+    // (changed or missing files are left out and added to errors, like groups left with a single
+    // file, with fail_fast the groups after the first one are left out as well)
+    pub fn verify(self, fail_fast: bool, errors: &mut Vec<Error>) -> Vec<DuplicateGroup> {
+        let roots: Vec<PathBuf> = self.roots.into_iter().chain(self.references).collect();
+        let mut groups = Vec::new();
+        let before = errors.len();
+        for group in self.duplicates {
+            if fail_fast && errors.len() > before {
+                break;
+            }
+            let mut files: Vec<FileData> = group.files.into_iter()
+                .filter_map(|f| verify_file(f, &roots).map_err(|e| errors.push(e)).ok())
+                .collect();
+            if files.len() > 1 {
+                files.sort_by(compare_file_data);
+                groups.push(DuplicateGroup { key: group.key, files });
+            }
+        }
+        groups
+    }
+}
+
+// the current state of a saved file, if it still has the size and contents (or link target) it was saved with
+fn verify_file(saved: SavedFile, roots: &[PathBuf]) -> Result<FileData> {
+    let path = saved.path;
+    let root = root_of(&path, roots).to_path_buf();
+    let reference = saved.reference;
+    let metadata = path.symlink_metadata().map_err(|e| Error::io(&path, e))?;
+    let hash = if metadata.is_symlink() {
+        Some(target_hash(&path).map_err(|e| Error::io(&path, e))?)
+    } else if metadata.is_file() && metadata.len() == saved.size {
+        Some(hash::hash_file(&path).map_err(|e| Error::io(&path, e))?)
+    } else {
+        None
+    };
+    if hash.is_none() || hash != saved.hash {
+        return Err(Error::io(&path, io::Error::other("changed since the scan, skipping it")));
+    }
+    Ok(FileData {
+        // the hash of a symbolic link is only used for the saved scan
+        hash: if metadata.is_symlink() { None } else { hash },
+        filepath: path,
+        root,
+        reference,
+        metadata,
+    })
+}
+
+// the root a saved file was found below
+pub fn root_of<'a>(path: &'a Path, roots: &'a [PathBuf]) -> &'a Path {
+    roots.iter()
+        .find(|root| path.starts_with(root))
+        .map_or_else(|| path.parent().unwrap_or(path), |root| root.as_path())
+}
+
+// the hash of the target of a symbolic link, so a changed target is noticed like changed contents
+fn target_hash(path: &Path) -> io::Result<Digest> {
+    let mut hasher = Sha256::new();
+    hasher.update(scan::link_target(path)?.as_os_str().as_bytes());
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::COMPOUND_EXTENSIONS;
+    use crate::scan::Scanner;
+
+    // an empty directory of its own for every test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("results");
+        let root = dir.join("root");
+        let reference = dir.join("reference");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&reference).unwrap();
+        std::fs::write(root.join("a\tb (1).txt"), "same").unwrap();
+        std::fs::write(reference.join("a\tb.txt"), "same").unwrap();
+        std::fs::write(root.join("c.txt"), "one").unwrap();
+        std::fs::write(root.join("c (1).txt"), "two").unwrap();
+
+        let extensions: Vec<String> = COMPOUND_EXTENSIONS.iter().map(|ext| ext.to_string()).collect();
+        let rule = Regex::new(r"^(?P<base>.+)\t~(?P<ext>)$").unwrap();
+        let normalizer = Normalizer::new(&[Pattern::Numbered, Pattern::Dash], vec![rule], &extensions).with_folding(true, false);
+        let scanner = Scanner::new(normalizer.clone()).cross_directory(true).references(vec![reference.clone()]);
+        let mut errors = Vec::new();
+        let roots = [root.clone()];
+        let groups = scanner.scan(&roots, &mut errors).unwrap();
+        assert!(errors.is_empty());
+
+        let path = dir.join("scan");
+        save(&path, &roots, std::slice::from_ref(&reference), &normalizer, &groups).unwrap();
+        let saved = load(&path).unwrap();
+        assert_eq!(saved.roots, roots);
+        assert_eq!(saved.references, [reference.as_path()]);
+        assert_eq!(saved.patterns, [Pattern::Numbered, Pattern::Dash]);
+        assert_eq!(saved.rules.iter().map(Regex::as_str).collect::<Vec<_>>(), [r"^(?P<base>.+)\t~(?P<ext>)$"]);
+        assert_eq!(saved.compound_extensions, extensions);
+        assert!(saved.ignore_case && !saved.unicode_normalize);
+
+        assert_eq!(saved.duplicates.len(), 1);
+        let group = &saved.duplicates[0];
+        assert_eq!(group.key, Path::new("a\tb.txt"));
+        let mut files: Vec<(&Path, bool)> = group.files.iter().map(|f| (f.path.as_path(), f.reference)).collect();
+        files.sort();
+        assert_eq!(files, [(reference.join("a\tb.txt").as_path(), true), (root.join("a\tb (1).txt").as_path(), false)]);
+        assert!(group.files.iter().all(|f| f.size == 4 && f.hash.is_some()));
+        assert_eq!(saved.reclaimable_bytes(), 4);
+        assert_eq!(saved.collisions.len(), 1);
+        assert_eq!(saved.collisions[0].files.len(), 2);
+
+        // the normalizer of the scan recognises copies the same way
+        let normalizer = saved.normalizer();
+        assert_eq!(normalizer.normalize(std::ffi::OsStr::new("x-2.txt")), "x.txt");
+        assert_eq!(normalizer.normalize(std::ffi::OsStr::new("x\t~")), "x");
+        assert_eq!(normalizer.key(std::ffi::OsStr::new("X.TXT")), "x.txt");
+
+        // a file changed since the scan is left out
+        std::fs::write(root.join("a\tb (1).txt"), "changed").unwrap();
+        let mut errors = Vec::new();
+        assert!(saved.verify(false, &mut errors).is_empty());
+        assert_eq!(errors.len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn malformed() {
+        let dir = temp_dir("malformed");
+        let path = dir.join("scan");
+        let cases: &[(&str, &str)] = &[
+            ("bogus\tline", "line 1"),
+            ("scan\tnow\nroot", "line 2"),
+            ("pattern\tunknown", "line 1"),
+            ("rule\t(", "line 1"),
+            ("duplicate\tx\tkey\t/a\t1\t-\t-", "line 1"),
+            ("duplicate\t0\tkey\t/a\tbig\t-\t-", "line 1"),
+            ("duplicate\t0\tkey\t/a\t1\tnothex\t-", "line 1"),
+            ("duplicate\t0\tkey\t/a\t1\t-", "line 1"),
+            ("scan\tnow\nduplicate\t0\tkey\t/a\t1\t-\tmaybe", "line 2"),
+        ];
+        for &(contents, line) in cases {
+            std::fs::write(&path, contents).unwrap();
+            let error = load(&path).unwrap_err().to_string();
+            assert!(error.contains(&format!("{}: malformed scan result", line)), "{:?} gave {}", contents, error);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,538 @@
+// walking a directory tree and finding the files that are copies of each other
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::{DirEntry, WalkDir};
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::hash::{self, Digest};
+use crate::normalize::Normalizer;
+
+// the struct for comparing the files for checking duplicates
+#[derive(Debug)]
+pub struct FileData {
+    pub filepath: PathBuf,
+    // the root directory the file was found below
+    pub root: PathBuf,
+    // whether the file is inside a reference directory, so it's never changed
+    pub reference: bool,
+    pub metadata: Metadata,
+    // content hash, only computed for files that share their size with another file
+    pub hash: Option<Digest>,
+}
+
+impl FileData {
+    // the bytes freed by removing the file (nothing for a symbolic link, its target stays)
+    pub fn reclaimable_size(&self) -> u64 {
+        if self.metadata.is_symlink() { 0 } else { self.metadata.len() }
+    }
+}
+
+// a group of files sharing the same normalized name
+#[derive(Debug)]
+pub struct DuplicateGroup {
+    // the normalized name (including the parent directory relative to the root, unless grouping
+    // across directories)
+    pub key: PathBuf,
+    pub files: Vec<FileData>,
+}
+
+// the outcome of verifying the contents of name groups
+#[derive(Debug, Default)]
+pub struct VerifiedGroups {
+    // files with identical contents (oldest file first)
+    pub duplicates: Vec<DuplicateGroup>,
+    // files whose name matched but whose contents differ from every other file of the group
+    pub collisions: Vec<DuplicateGroup>,
+}
+
+// filter function for ignoring hidden files
+fn is_hidden(dir_entry: &DirEntry) -> bool {
+    dir_entry.file_name().as_bytes().starts_with(b".")
+}
+
+// the timestamp (or fallback) that decided the order of two files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    Birth,
+    Modified,
+    Changed,
+    Inode,
+    Path,
+}
+
+impl std::fmt::Display for TimeSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TimeSource::Birth => "birth time",
+            TimeSource::Modified => "modification time",
+            TimeSource::Changed => "status change time",
+            TimeSource::Inode => "inode number",
+            TimeSource::Path => "path",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// compare file data based on their metadata
+pub fn compare_file_data(fd1: &FileData, fd2: &FileData) -> Ordering {
+    compare_file_times(fd1, fd2).0
+}
+
+// compare file data based on their timestamps (oldest first), returning which one decided
+// (not every filesystem records a birth time, so this falls back to the modified and
+// changed times, and finally to the inode and path to stay deterministic)
+pub fn compare_file_times(fd1: &FileData, fd2: &FileData) -> (Ordering, TimeSource) {
+    compare_times(&FileTimes::of(&fd1.metadata), &FileTimes::of(&fd2.metadata), &fd1.filepath, &fd2.filepath)
+}
+
+// the timestamps (and inode) files are ordered by
+#[derive(Debug, Clone, Copy)]
+struct FileTimes {
+    // not every filesystem (or kernel) reports these
+    birth: Option<SystemTime>,
+    modified: Option<SystemTime>,
+    // seconds and nanoseconds
+    changed: (i64, i64),
+    inode: u64,
+}
+
+impl FileTimes {
+    fn of(metadata: &Metadata) -> Self {
+        FileTimes {
+            birth: metadata.created().ok(),
+            modified: metadata.modified().ok(),
+            changed: (metadata.ctime(), metadata.ctime_nsec()),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+fn compare_times(t1: &FileTimes, t2: &FileTimes, path1: &Path, path2: &Path) -> (Ordering, TimeSource) {
+    // creation times of file data
+    if let (Some(c1), Some(c2)) = (t1.birth, t2.birth)
+        && c1 != c2
+    {
+        return (c1.cmp(&c2), TimeSource::Birth);
+    }
+
+    // modified times of file data
+    if let (Some(m1), Some(m2)) = (t1.modified, t2.modified)
+        && m1 != m2
+    {
+        return (m1.cmp(&m2), TimeSource::Modified);
+    }
+
+    // status change times of file data
+    if t1.changed != t2.changed {
+        return (t1.changed.cmp(&t2.changed), TimeSource::Changed);
+    }
+
+    let ordering = t1.inode.cmp(&t2.inode);
+    if ordering != Ordering::Equal {
+        return (ordering, TimeSource::Inode);
+    }
+    (path1.cmp(path2), TimeSource::Path)
+}
+
+// finds the files with the same normalized name and identical contents in a directory tree
+//
+//     let scanner = Scanner::new(normalizer).cross_directory(true).byte_compare(true);
+//     let groups = scanner.scan(&[PathBuf::from("photos")], &mut errors)?;
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    normalizer: Normalizer,
+    cross_directory: bool,
+    include_symlinks: bool,
+    byte_compare: bool,
+    references: Vec<PathBuf>,
+    filter: Filter,
+    fail_fast: bool,
+}
+
+impl Scanner {
+    pub fn new(normalizer: Normalizer) -> Self {
+        Scanner {
+            normalizer,
+            cross_directory: false,
+            include_symlinks: false,
+            byte_compare: false,
+            references: Vec::new(),
+            filter: Filter::default(),
+            fail_fast: false,
+        }
+    }
+
+    // group files with the same name across the whole tree instead of per directory
+    pub fn cross_directory(mut self, cross_directory: bool) -> Self {
+        self.cross_directory = cross_directory;
+        self
+    }
+
+    // also group symbolic links, which are duplicates if they point to the same target
+    pub fn include_symlinks(mut self, include_symlinks: bool) -> Self {
+        self.include_symlinks = include_symlinks;
+        self
+    }
+
+    // compare files byte for byte after their hashes matched
+    pub fn byte_compare(mut self, byte_compare: bool) -> Self {
+        self.byte_compare = byte_compare;
+        self
+    }
+
+    // directories scanned along with the roots whose files are never deleted or renamed, but
+    // make the copies of them in the roots duplicates
+    pub fn references(mut self, references: Vec<PathBuf>) -> Self {
+        self.references = references;
+        self
+    }
+
+    // only scan the files (and descend into the directories) the filter allows
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    // stop at the first entry or file that can't be read, instead of skipping it and carrying on
+    // (the error still ends up in errors, along with what was found until then)
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    pub fn normalizer(&self) -> &Normalizer {
+        &self.normalizer
+    }
+
+    // the duplicates in a directory tree, together with the names shared by files whose contents differ
+    //
+    // entries that can't be read are skipped and added to errors (or stop the scan with
+    // fail_fast), only an unreadable directory itself is fatal
+    pub fn scan(&self, roots: &[PathBuf], errors: &mut Vec<Error>) -> Result<VerifiedGroups> {
+        let mut name_groups = self.group(roots, errors)?;
+        // only names shared by several files can be duplicates
+        name_groups.retain(|_, v| v.len() > 1);
+        Ok(self.verify(name_groups, errors))
+    }
+
+    // group all files below the roots by their normalized name, files without any copies included
+    // (files are only grouped with files in the same directory relative to their root, unless
+    // cross_directory is set, so "a/x.txt" of one root is grouped with "a/x (1).txt" of another)
+    //
+    // only regular files are grouped, and symbolic links if include_symlinks is set
+    pub fn group(&self, roots: &[PathBuf], errors: &mut Vec<Error>) -> Result<HashMap<PathBuf, Vec<FileData>>> {
+        let mut duplicate_map: HashMap<PathBuf, Vec<FileData>> = HashMap::new();
+        let mut references = Vec::new();
+        for reference in &self.references {
+            references.push(reference.canonicalize().map_err(|e| Error::io(reference, e))?);
+        }
+        let all_roots: Vec<PathBuf> = roots.iter().chain(&self.references).cloned().collect();
+        let before = errors.len();
+        for (root, outer) in nested_roots(&all_roots)? {
+            if self.fail_fast && errors.len() > before {
+                break;
+            }
+            if outer.is_none() {
+                self.walk(&root, &references, &mut duplicate_map, errors)?;
+            }
+        }
+
+        for values in duplicate_map.values_mut() {
+            // sort the vector based on their metadata (creation and modified time, oldest file first)
+            values.sort_by(compare_file_data);
+        }
+        Ok(duplicate_map)
+    }
+
+    // add the files below a root to their name groups
+    // (references are the canonical reference directories, which may also lie inside the root)
+    fn walk(&self, root: &Path, references: &[PathBuf], duplicate_map: &mut HashMap<PathBuf, Vec<FileData>>, errors: &mut Vec<Error>) -> Result<()> {
+        let canonical_root = root.canonicalize().map_err(|e| Error::io(root, e))?;
+        let walker = WalkDir::new(root).into_iter();
+        // (the directory itself is never hidden or filtered, even if it is given as ".")
+        let allowed = |e: &DirEntry| {
+            let relative = e.path().strip_prefix(root).unwrap_or(e.path());
+            !is_hidden(e) && self.filter.allows(relative, e.file_type().is_dir())
+        };
+        let before = errors.len();
+        for entry in walker.filter_entry(|e| e.depth() == 0 || allowed(e)) {
+            if self.fail_fast && errors.len() > before {
+                break;
+            }
+            let e = match entry {
+                Ok(e) => e,
+                Err(e) if e.depth() == 0 => return Err(Error::Walk(e)),
+                Err(e) => {
+                    errors.push(Error::Walk(e));
+                    continue;
+                }
+            };
+            // (walkdir doesn't follow symlinks, so the file type is the one of the link itself)
+            let file_type = e.file_type();
+            if !(file_type.is_file() || self.include_symlinks && file_type.is_symlink()) {
+                continue;
+            }
+            let metadata = match e.path().symlink_metadata() {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    errors.push(Error::io(e.path(), err));
+                    continue;
+                }
+            };
+            // add all same entries into hash map
+            let basename = self.normalizer.key(e.file_name());
+            let key = match e.path().parent() {
+                Some(parent) if !self.cross_directory => parent.strip_prefix(root).unwrap_or(parent).join(basename),
+                _ => PathBuf::from(basename),
+            };
+
+            let relative = e.path().strip_prefix(root).unwrap_or(e.path());
+            let reference = references.iter().any(|r| canonical_root.join(relative).starts_with(r));
+
+            // add the path to the entry of its basename (creating the entry if needed)
+            let file_data = FileData {
+                filepath: e.path().to_path_buf(),
+                root: root.to_path_buf(),
+                reference,
+                metadata,
+                hash: None,
+            };
+            duplicate_map.entry(key).or_default().push(file_data);
+        }
+        Ok(())
+    }
+
+    // verify that the files of each name group have identical contents
+    pub fn verify(&self, name_groups: HashMap<PathBuf, Vec<FileData>>, errors: &mut Vec<Error>) -> VerifiedGroups {
+        let mut verified = VerifiedGroups::default();
+        let before = errors.len();
+        for (key, files) in name_groups {
+            if self.fail_fast && errors.len() > before {
+                break;
+            }
+            let (duplicates, unmatched) = split_by_content(files, self.byte_compare, self.fail_fast, errors);
+            for files in duplicates {
+                verified.duplicates.push(DuplicateGroup { key: key.clone(), files });
+            }
+            if !unmatched.is_empty() {
+                verified.collisions.push(DuplicateGroup { key, files: unmatched });
+            }
+        }
+        verified
+    }
+}
+
+// split the files of a name group into sets of identical contents
+// (size first, then sha-256 and optionally a byte for byte comparison)
+//
+// with fail_fast, the files after the first one that can't be read are left out
+fn split_by_content(files: Vec<FileData>, byte_compare: bool, fail_fast: bool, errors: &mut Vec<Error>) -> (Vec<Vec<FileData>>, Vec<FileData>) {
+    let before = errors.len();
+    // symlinks are compared by their targets instead of the contents of the files they point to
+    let (links, files): (Vec<FileData>, Vec<FileData>) = files.into_iter().partition(|f| f.metadata.is_symlink());
+    let (linked, unmatched_links) = split_by_target(links, fail_fast, errors);
+
+    // files with a unique size can't have a duplicate, so they are never hashed
+    let mut size_count: HashMap<u64, usize> = HashMap::new();
+    for f in &files {
+        *size_count.entry(f.metadata.len()).or_default() += 1;
+    }
+
+    let mut sets: Vec<Vec<FileData>> = Vec::new();
+    let mut unmatched = unmatched_links;
+    for mut f in files {
+        if fail_fast && errors.len() > before {
+            break;
+        }
+        if size_count[&f.metadata.len()] < 2 {
+            unmatched.push(f);
+            continue;
+        }
+        match hash::hash_file(&f.filepath) {
+            Ok(digest) => f.hash = Some(digest),
+            Err(e) => {
+                // a file that can't be read can't be proven to be a duplicate
+                errors.push(Error::io(&f.filepath, e));
+                continue;
+            }
+        }
+
+        // find the set of files with the same size and hash (keeping the sorting order)
+        let set = sets.iter_mut().find(|set| {
+            let first = &set[0];
+            if first.metadata.len() != f.metadata.len() || first.hash != f.hash {
+                return false;
+            }
+            if !byte_compare {
+                return true;
+            }
+            match hash::same_contents(&first.filepath, &f.filepath) {
+                Ok(same) => same,
+                Err(e) => {
+                    errors.push(Error::io(&f.filepath, e));
+                    false
+                }
+            }
+        });
+        match set {
+            Some(set) => set.push(f),
+            None => sets.push(vec![f]),
+        }
+    }
+
+    // sets with a single file didn't match any other file
+    let (mut duplicates, singles): (Vec<_>, Vec<_>) = sets.into_iter().partition(|set| set.len() > 1);
+    duplicates.extend(linked);
+    unmatched.extend(singles.into_iter().flatten());
+    unmatched.sort_by(compare_file_data);
+    (duplicates, unmatched)
+}
+
+// split symbolic links into sets pointing to the same target, returning them like split_by_content
+fn split_by_target(links: Vec<FileData>, fail_fast: bool, errors: &mut Vec<Error>) -> (Vec<Vec<FileData>>, Vec<FileData>) {
+    let before = errors.len();
+    let mut sets: Vec<(PathBuf, Vec<FileData>)> = Vec::new();
+    for f in links {
+        if fail_fast && errors.len() > before {
+            break;
+        }
+        let target = match link_target(&f.filepath) {
+            Ok(target) => target,
+            Err(e) => {
+                errors.push(Error::io(&f.filepath, e));
+                continue;
+            }
+        };
+        match sets.iter_mut().find(|(t, _)| *t == target) {
+            Some((_, set)) => set.push(f),
+            None => sets.push((target, vec![f])),
+        }
+    }
+    let (duplicates, singles): (Vec<_>, Vec<_>) = sets.into_iter().map(|(_, set)| set).partition(|set| set.len() > 1);
+    (duplicates, singles.into_iter().flatten().collect())
+}
+
+// every root together with the root it is inside of (or given twice as), if any, since
+// scanning such a root again would find the same files twice
+pub fn nested_roots(roots: &[PathBuf]) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+    let mut canonical = Vec::new();
+    for root in roots {
+        canonical.push(root.canonicalize().map_err(|e| Error::io(root, e))?);
+    }
+    Ok(canonical.iter().enumerate().map(|(i, path)| {
+        let outer = canonical.iter().enumerate()
+            .position(|(j, other)| path.starts_with(other) && (other != path || j < i));
+        (roots[i].clone(), outer.map(|j| roots[j].clone()))
+    }).collect())
+}
+
+// the absolute target of a symbolic link, with a relative target resolved against the directory
+// of the link and "." and ".." removed (lexically, without following anything)
+pub(crate) fn link_target(path: &Path) -> std::io::Result<PathBuf> {
+    let target = std::fs::read_link(path)?;
+    let link = std::path::absolute(path)?;
+    let joined = link.parent().map_or(target.clone(), |parent| parent.join(&target));
+    let mut resolved = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            // (".." of the root is the root itself)
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn link_targets() {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-links-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        let cases: &[(&str, PathBuf, PathBuf)] = &[
+            ("a/plain", PathBuf::from("t"), dir.join("a/t")),
+            ("a/dot", PathBuf::from("./t"), dir.join("a/t")),
+            ("a/up", PathBuf::from("../t"), dir.join("t")),
+            ("a/b/up", PathBuf::from("../../t"), dir.join("t")),
+            ("a/b/around", PathBuf::from("../b/./../../t"), dir.join("t")),
+            ("a/absolute", dir.join("x/../t"), dir.join("t")),
+            ("a/above_root", PathBuf::from("/../../t"), PathBuf::from("/t")),
+            // targets don't have to exist
+            ("a/missing", PathBuf::from("b/../missing"), dir.join("a/missing")),
+        ];
+        for (link, target, expected) in cases {
+            symlink(target, dir.join(link)).unwrap();
+            assert_eq!(link_target(&dir.join(link)).unwrap(), *expected, "{}", link);
+        }
+
+        // relative to the current directory
+        let cwd = std::env::current_dir().unwrap();
+        let relative = crate::link::relative_path(&cwd, &dir.join("a/up"));
+        assert_eq!(link_target(&relative).unwrap(), dir.join("t"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn time_fallbacks() {
+        let time = |seconds: u64| Some(UNIX_EPOCH + Duration::from_secs(seconds));
+        let times = |birth, modified, changed: i64, inode| FileTimes { birth, modified, changed: (changed, 0), inode };
+        let (a, b) = (Path::new("/a"), Path::new("/b"));
+        let cases: &[(FileTimes, FileTimes, Ordering, TimeSource)] = &[
+            (times(time(1), time(9), 9, 9), times(time(2), time(1), 1, 1), Ordering::Less, TimeSource::Birth),
+            (times(time(2), time(1), 1, 1), times(time(1), time(9), 9, 9), Ordering::Greater, TimeSource::Birth),
+            // equal or missing birth times fall back to the modification time
+            (times(time(1), time(1), 9, 9), times(time(1), time(2), 1, 1), Ordering::Less, TimeSource::Modified),
+            (times(None, time(2), 1, 1), times(None, time(1), 9, 9), Ordering::Greater, TimeSource::Modified),
+            (times(time(1), time(1), 9, 9), times(None, time(2), 1, 1), Ordering::Less, TimeSource::Modified),
+            // then to the status change time
+            (times(None, time(1), 1, 9), times(None, time(1), 2, 1), Ordering::Less, TimeSource::Changed),
+            (times(None, None, 2, 1), times(None, None, 1, 9), Ordering::Greater, TimeSource::Changed),
+            // then to the inode, and the path if even that is the same
+            (times(time(1), time(1), 1, 1), times(time(1), time(1), 1, 2), Ordering::Less, TimeSource::Inode),
+            (times(None, None, 1, 1), times(None, None, 1, 1), Ordering::Less, TimeSource::Path),
+        ];
+        for (i, (t1, t2, ordering, source)) in cases.iter().enumerate() {
+            assert_eq!(compare_times(t1, t2, a, b), (*ordering, *source), "case {}", i);
+        }
+        assert_eq!(compare_times(&cases[8].0, &cases[8].1, b, a), (Ordering::Greater, TimeSource::Path));
+    }
+
+    #[test]
+    fn fail_fast() {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-fail-fast-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let scanner = Scanner::new(Normalizer::new(&[crate::normalize::Pattern::Numbered], Vec::new(), &[]));
+        // two name groups, with a file of each disappearing before the contents are compared
+        let name_groups = || {
+            for name in ["a.txt", "a (1).txt", "b.txt", "b (1).txt"] {
+                std::fs::write(dir.join(name), "same").unwrap();
+            }
+            let name_groups = scanner.group(std::slice::from_ref(&dir), &mut Vec::new()).unwrap();
+            std::fs::remove_file(dir.join("a (1).txt")).unwrap();
+            std::fs::remove_file(dir.join("b (1).txt")).unwrap();
+            name_groups
+        };
+
+        let mut errors = Vec::new();
+        scanner.verify(name_groups(), &mut errors);
+        assert_eq!(errors.len(), 2);
+        let mut errors = Vec::new();
+        scanner.clone().fail_fast(true).verify(name_groups(), &mut errors);
+        assert_eq!(errors.len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+// thin wrappers around the libc functions std doesn't expose
+use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, CString};
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// broken down local time, as filled in by localtime_r
+#[repr(C)]
+struct Tm {
+    tm_sec: c_int,
+    tm_min: c_int,
+    tm_hour: c_int,
+    tm_mday: c_int,
+    tm_mon: c_int,
+    tm_year: c_int,
+    tm_wday: c_int,
+    tm_yday: c_int,
+    tm_isdst: c_int,
+    tm_gmtoff: c_long,
+    tm_zone: *const c_char,
+}
+
+// argument of the FIDEDUPERANGE ioctl with a single destination
+#[repr(C)]
+struct FileDedupeRange {
+    src_offset: u64,
+    src_length: u64,
+    dest_count: u16,
+    reserved1: u16,
+    reserved2: u32,
+    info: FileDedupeRangeInfo,
+}
+
+#[repr(C)]
+struct FileDedupeRangeInfo {
+    dest_fd: i64,
+    dest_offset: u64,
+    bytes_deduped: u64,
+    status: i32,
+    reserved: u32,
+}
+
+// _IOWR(0x94, 54, struct file_dedupe_range)
+const FIDEDUPERANGE: c_ulong = 0xc0189436;
+const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+// the kernel caps the length of a single request (btrfs at 16 MiB)
+const DEDUPE_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+// paths relative to the current directory for the *at functions
+const AT_FDCWD: c_int = -100;
+const RENAME_NOREPLACE: c_uint = 1;
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+
+unsafe extern "C" {
+    fn getuid() -> u32;
+    fn renameat2(olddirfd: c_int, oldpath: *const c_char, newdirfd: c_int, newpath: *const c_char, flags: c_uint) -> c_int;
+    fn localtime_r(time: *const c_long, result: *mut Tm) -> *mut Tm;
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+// the real user id of the process
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and always succeeds
+    unsafe { getuid() }
+}
+
+// formats seconds since the epoch as local time in the form "YYYY-MM-DDThh:mm:ss"
+pub fn format_local_time(seconds: i64) -> io::Result<String> {
+    let time = seconds as c_long;
+    let mut tm = Tm {
+        tm_sec: 0,
+        tm_min: 0,
+        tm_hour: 0,
+        tm_mday: 0,
+        tm_mon: 0,
+        tm_year: 0,
+        tm_wday: 0,
+        tm_yday: 0,
+        tm_isdst: 0,
+        tm_gmtoff: 0,
+        tm_zone: std::ptr::null(),
+    };
+    // SAFETY: both pointers are valid for the duration of the call
+    if unsafe { localtime_r(&time, &mut tm) }.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    ))
+}
+
+// share the extents of the first len bytes of src with dest, if their contents are identical
+// (returns the number of bytes deduplicated by the filesystem)
+pub fn dedupe_range(src: &File, dest: &File, len: u64) -> io::Result<u64> {
+    let mut offset = 0;
+    while offset < len {
+        let mut range = FileDedupeRange {
+            src_offset: offset,
+            src_length: (len - offset).min(DEDUPE_CHUNK_SIZE),
+            dest_count: 1,
+            reserved1: 0,
+            reserved2: 0,
+            info: FileDedupeRangeInfo {
+                dest_fd: dest.as_raw_fd() as i64,
+                dest_offset: offset,
+                bytes_deduped: 0,
+                status: 0,
+                reserved: 0,
+            },
+        };
+        // SAFETY: range is a valid file_dedupe_range with room for exactly dest_count infos
+        if unsafe { ioctl(src.as_raw_fd(), FIDEDUPERANGE, &mut range as *mut FileDedupeRange) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if range.info.status < 0 {
+            return Err(io::Error::from_raw_os_error(-range.info.status));
+        }
+        if range.info.status == FILE_DEDUPE_RANGE_DIFFERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the contents differ"));
+        }
+        if range.info.bytes_deduped == 0 {
+            break;
+        }
+        offset += range.info.bytes_deduped;
+    }
+    Ok(offset)
+}
+
+// rename a file, failing with AlreadyExists instead of replacing an existing file at to
+//
+// filesystems without RENAME_NOREPLACE fall back to a hard link (which never replaces
+// anything) and removing the old name, and as a last resort to checking before renaming
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let old = CString::new(from.as_os_str().as_bytes())?;
+    let new = CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid nul terminated strings for the duration of the call
+    if unsafe { renameat2(AT_FDCWD, old.as_ptr(), AT_FDCWD, new.as_ptr(), RENAME_NOREPLACE) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    if !matches!(e.raw_os_error(), Some(EINVAL | ENOSYS)) {
+        return Err(e);
+    }
+
+    match std::fs::hard_link(from, to) {
+        Ok(()) => return std::fs::remove_file(from),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+        Err(_) => {}
+    }
+    if to.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+    }
+    std::fs::rename(from, to)
+}
@@ -0,0 +1,224 @@
+// moving files to the trash as described by the freedesktop.org trash specification
+// (https://specifications.freedesktop.org/trash-spec/latest/)
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use crate::{journal, sys, xdg};
+
+// a trash directory together with the directory its paths are relative to
+struct TrashDir {
+    path: PathBuf,
+    // the top directory for per-mount trash directories (paths are stored relative to it)
+    topdir: Option<PathBuf>,
+}
+
+// move a file to the trash directory of its filesystem, returning its new location
+pub fn trash_file(path: &Path) -> io::Result<PathBuf> {
+    let path = absolute_path(path)?;
+    let device = path.symlink_metadata()?.dev();
+    let trash = find_trash_dir(&path, device)?;
+    move_to_trash(&path, &trash)
+}
+
+// move a file (given by its absolute path) into a trash directory
+fn move_to_trash(path: &Path, trash: &TrashDir) -> io::Result<PathBuf> {
+    let files = trash.path.join("files");
+    let info = trash.path.join("info");
+    create_private_dir(&files)?;
+    create_private_dir(&info)?;
+
+    // the path stored in the trash info file
+    let original = match &trash.topdir {
+        Some(topdir) => path.strip_prefix(topdir).unwrap_or(path),
+        None => path,
+    };
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(original.as_os_str().as_bytes()),
+        sys::format_local_time(journal::unix_time())?
+    );
+
+    // reserve a unique name by creating the info file first, as required by the specification
+    let filename = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut counter = 1;
+    loop {
+        let mut name = filename.to_os_string();
+        if counter > 1 {
+            name.push(format!(".{}", counter));
+        }
+        counter += 1;
+
+        let target = files.join(&name);
+        let mut info_name = name;
+        info_name.push(".trashinfo");
+        let info_file = info.join(info_name);
+        if target.symlink_metadata().is_ok() {
+            continue;
+        }
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&info_file) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+
+        let moved = file.write_all(contents.as_bytes()).and_then(|_| std::fs::rename(path, &target));
+        if let Err(e) = moved {
+            // don't leave a dangling info file behind
+            let _ = std::fs::remove_file(&info_file);
+            return Err(e);
+        }
+        return Ok(target);
+    }
+}
+
+// pick the trash directory for a file living on the given device
+fn find_trash_dir(path: &Path, device: u64) -> io::Result<TrashDir> {
+    let home_trash = home_trash_dir()?;
+    create_private_dir(&home_trash)?;
+    if home_trash.metadata()?.dev() == device {
+        return Ok(TrashDir { path: home_trash, topdir: None });
+    }
+
+    let topdir = mount_point(path, device)?;
+    let uid = sys::current_uid();
+
+    // an administrator created $topdir/.Trash, which has to be a sticky directory (not a symlink)
+    let admin_trash = topdir.join(".Trash");
+    if let Ok(metadata) = admin_trash.symlink_metadata()
+        && metadata.is_dir()
+        && metadata.permissions().mode() & 0o1000 != 0
+    {
+        let user_trash = admin_trash.join(uid.to_string());
+        if create_private_dir(&user_trash).is_ok() {
+            return Ok(TrashDir { path: user_trash, topdir: Some(topdir) });
+        }
+    }
+
+    // otherwise the user's own $topdir/.Trash-$uid
+    let user_trash = topdir.join(format!(".Trash-{}", uid));
+    create_private_dir(&user_trash)?;
+    let metadata = user_trash.symlink_metadata()?;
+    if !metadata.is_dir() || metadata.uid() != uid {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a trash directory owned by the current user", user_trash.display()),
+        ));
+    }
+    Ok(TrashDir { path: user_trash, topdir: Some(topdir) })
+}
+
+// $XDG_DATA_HOME/Trash, defaulting to ~/.local/share/Trash
+fn home_trash_dir() -> io::Result<PathBuf> {
+    Ok(xdg::base_dir("XDG_DATA_HOME", ".local/share")?.join("Trash"))
+}
+
+// the topmost directory of the path that is still on the same device
+fn mount_point(path: &Path, device: u64) -> io::Result<PathBuf> {
+    let mut topdir = path.parent().unwrap_or(path);
+    while let Some(parent) = topdir.parent() {
+        if parent.metadata()?.dev() != device {
+            break;
+        }
+        topdir = parent;
+    }
+    Ok(topdir.to_path_buf())
+}
+
+// absolute path of the file with a canonical parent, without resolving the file itself
+fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    let filename = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => std::env::current_dir()?,
+    };
+    Ok(parent.join(filename))
+}
+
+// create a directory (and its parents) only accessible by the user
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(path)
+}
+
+// percent encode a path the way urls are encoded (keeping the separators)
+fn percent_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+// the info file belonging to a file inside the files directory of a trash
+pub fn info_file(trashed: &Path) -> Option<PathBuf> {
+    let trash = trashed.parent()?.parent()?;
+    let mut name = trashed.file_name()?.to_os_string();
+    name.push(".trashinfo");
+    Some(trash.join("info").join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encoding() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"/home/user/a-b_c.d~", "/home/user/a-b_c.d~"),
+            (b"/with space/100%.txt", "/with%20space/100%25.txt"),
+            (b"/tab\tnewline\n", "/tab%09newline%0A"),
+            ("/café".as_bytes(), "/caf%C3%A9"),
+            (b"/caf\xe9", "/caf%E9"),
+        ];
+        for &(bytes, encoded) in cases {
+            assert_eq!(percent_encode(bytes), encoded, "{:?}", String::from_utf8_lossy(bytes));
+        }
+    }
+
+    #[test]
+    fn trashing() {
+        let dir = std::env::temp_dir().join(format!("uniquer-test-trash-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        let trash = TrashDir { path: dir.join("Trash"), topdir: None };
+        let info = |trashed: &Path| std::fs::read_to_string(info_file(trashed).unwrap()).unwrap();
+
+        let path = dir.join("a/x y.txt");
+        std::fs::write(&path, "1").unwrap();
+        let trashed = move_to_trash(&path, &trash).unwrap();
+        assert_eq!(trashed, dir.join("Trash/files/x y.txt"));
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_to_string(&trashed).unwrap(), "1");
+        let contents = info(&trashed);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines[..2], ["[Trash Info]".to_string(), format!("Path={}", percent_encode(path.as_os_str().as_bytes()))]);
+        // a local time like 2024-01-31T12:34:56
+        let date = lines[2].strip_prefix("DeletionDate=").unwrap();
+        assert!(date.len() == 19 && date.as_bytes()[10] == b'T', "{}", date);
+        assert_eq!(lines.len(), 3);
+
+        // files with the same name get a counter, skipping names taken by a file or an info file
+        std::fs::write(dir.join("Trash/info/x y.txt.3.trashinfo"), "").unwrap();
+        let mut names = Vec::new();
+        for contents in ["2", "3"] {
+            std::fs::write(&path, contents).unwrap();
+            let trashed = move_to_trash(&path, &trash).unwrap();
+            assert_eq!(std::fs::read_to_string(&trashed).unwrap(), contents);
+            assert!(info_file(&trashed).unwrap().exists());
+            names.push(trashed.file_name().unwrap().to_os_string());
+        }
+        assert_eq!(names, ["x y.txt.2", "x y.txt.4"]);
+
+        // per-mount trash directories store the path relative to their top directory
+        let mount_trash = TrashDir { path: dir.join(".Trash-1000"), topdir: Some(dir.clone()) };
+        std::fs::write(&path, "4").unwrap();
+        let trashed = move_to_trash(&path, &mount_trash).unwrap();
+        assert!(info(&trashed).contains("\nPath=a/x%20y.txt\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
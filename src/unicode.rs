@@ -0,0 +1,117 @@
+// unicode normalization (nfd) of file names for comparing them
+use crate::unicode_tables::{COMBINING_CLASSES, DECOMPOSITIONS};
+
+// hangul syllables, which decompose algorithmically into jamo
+const HANGUL_BASE: u32 = 0xAC00;
+const HANGUL_COUNT: u32 = 11172;
+const LEAD_BASE: u32 = 0x1100;
+const VOWEL_BASE: u32 = 0x1161;
+const TRAIL_BASE: u32 = 0x11A7;
+const VOWEL_COUNT: u32 = 21;
+const TRAIL_COUNT: u32 = 28;
+
+// the canonical decomposition (nfd) of a string, so "Café" is the same whether the é is
+// precomposed (as on windows and linux) or a combining accent (as on macos)
+pub fn nfd(s: &str) -> String {
+    let mut decomposed = String::with_capacity(s.len());
+    for c in s.chars() {
+        decompose(c, &mut decomposed);
+    }
+    reorder_combining_marks(decomposed)
+}
+
+fn decompose(c: char, out: &mut String) {
+    let code = c as u32;
+    if (HANGUL_BASE..HANGUL_BASE + HANGUL_COUNT).contains(&code) {
+        let index = code - HANGUL_BASE;
+        let jamo = [
+            Some(LEAD_BASE + index / (VOWEL_COUNT * TRAIL_COUNT)),
+            Some(VOWEL_BASE + index % (VOWEL_COUNT * TRAIL_COUNT) / TRAIL_COUNT),
+            Some(index % TRAIL_COUNT).filter(|&t| t != 0).map(|t| TRAIL_BASE + t),
+        ];
+        out.extend(jamo.into_iter().flatten().filter_map(char::from_u32));
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&c, |&(c, _)| c) {
+        Ok(index) => out.extend(DECOMPOSITIONS[index].1),
+        Err(_) => out.push(c),
+    }
+}
+
+// sorts each run of combining marks by their combining class (the canonical ordering)
+fn reorder_combining_marks(s: String) -> String {
+    if !s.chars().any(|c| combining_class(c) != 0) {
+        return s;
+    }
+    let mut chars: Vec<char> = s.chars().collect();
+    let mut start = 0;
+    while start < chars.len() {
+        if combining_class(chars[start]) == 0 {
+            start += 1;
+            continue;
+        }
+        let end = chars[start..].iter().position(|&c| combining_class(c) == 0).map_or(chars.len(), |len| start + len);
+        // a stable sort keeps marks of the same class in order
+        chars[start..end].sort_by_key(|&c| combining_class(c));
+        start = end;
+    }
+    chars.into_iter().collect()
+}
+
+fn combining_class(c: char) -> u8 {
+    let index = COMBINING_CLASSES.partition_point(|&(_, end, _)| end < c);
+    match COMBINING_CLASSES.get(index) {
+        Some(&(start, _, class)) if start <= c => class,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decompositions() {
+        let cases: &[(&str, &str)] = &[
+            ("", ""),
+            ("plain ascii (1).txt", "plain ascii (1).txt"),
+            ("\u{e9}", "e\u{301}"),
+            ("\u{c5}ngstr\u{f6}m", "A\u{30a}ngstro\u{308}m"),
+            // decompositions of decompositions
+            ("\u{1e09}", "c\u{327}\u{301}"),
+            ("\u{1d6}", "u\u{308}\u{304}"),
+            // singletons
+            ("\u{2126}", "\u{3a9}"),
+            ("\u{958}", "\u{915}\u{93c}"),
+            // compatibility decompositions are left alone
+            ("\u{fb01}", "\u{fb01}"),
+            // hangul syllables, with and without a trailing consonant
+            ("\u{ac00}", "\u{1100}\u{1161}"),
+            ("\u{ac01}", "\u{1100}\u{1161}\u{11a8}"),
+            // already decomposed text stays the same
+            ("e\u{301}", "e\u{301}"),
+        ];
+        for &(input, expected) in cases {
+            assert_eq!(nfd(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn canonical_ordering() {
+        // dot below (220) goes before dot above (230), whichever way they were written
+        assert_eq!(nfd("q\u{307}\u{323}"), "q\u{323}\u{307}");
+        assert_eq!(nfd("\u{1e9b}\u{323}"), "\u{17f}\u{323}\u{307}");
+        assert_eq!(nfd("\u{1e0b}\u{323}"), "d\u{323}\u{307}");
+        // marks of the same class keep their order
+        assert_eq!(nfd("a\u{301}\u{300}"), "a\u{301}\u{300}");
+        assert_eq!(nfd("a\u{300}\u{301}"), "a\u{300}\u{301}");
+    }
+
+    #[test]
+    fn combining_classes() {
+        assert_eq!(combining_class('a'), 0);
+        assert_eq!(combining_class('\u{301}'), 230);
+        assert_eq!(combining_class('\u{323}'), 220);
+        assert_eq!(combining_class('\u{93c}'), 7);
+    }
+}
@@ -0,0 +1,15 @@
+// the base directories of the freedesktop.org base directory specification
+// (https://specifications.freedesktop.org/basedir-spec/latest/)
+use std::io;
+use std::path::{Path, PathBuf};
+
+// the directory named by the variable (e.g. XDG_STATE_HOME), defaulting to the given directory
+// below the home directory (e.g. ".local/state") when it isn't set or not absolute
+pub(crate) fn base_dir(variable: &str, default: &str) -> io::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(variable).filter(|d| Path::new(d).is_absolute()) {
+        return Ok(PathBuf::from(dir));
+    }
+    let home = std::env::var_os("HOME")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("neither {} nor HOME is set", variable)))?;
+    Ok(PathBuf::from(home).join(default))
+}